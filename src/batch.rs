@@ -0,0 +1,492 @@
+// upload many files at once, each with its own link
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use indicatif::{MultiProgress, ProgressBar};
+use reqwest::Client;
+use rusty_s3::Bucket;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::checksum::hex_digest;
+use crate::config::{self, BatchFormat, BatchOptions, Config};
+use crate::dedup::{self, Candidates, Reuse};
+use crate::parts::{self, spawn_part_reader};
+use crate::progress::upload_progress_bar;
+use crate::upload::{self, MultipartUpload, ObjectHeaders, UploadError, Uploaded};
+use crate::{content_type, link, metadata, verify, MULTIPART_LIMIT};
+
+/// One line of the table of links. Everything but `path` is missing if the
+/// file could not be shared, and `error` says why.
+#[derive(Debug, Default, Serialize)]
+struct Row {
+    path: String,
+    key: Option<String>,
+    url: Option<String>,
+    size: Option<u64>,
+    expires: Option<String>,
+    sha256: Option<String>,
+    error: Option<String>,
+}
+
+/// Upload every file of `options` on its own, `options.jobs` at a time, and
+/// write a table with one link per file.
+///
+/// A file that can not be shared gets a row with the error, the others are
+/// uploaded nonetheless. Exits with an error code if any file failed.
+///
+/// On Ctrl-C or SIGTERM, no further file is started and unfinished multipart
+/// uploads are aborted before the process exits.
+pub async fn batch(config: &Config, options: &BatchOptions, bucket: &Bucket, client: &Client) {
+    let mut entries = options.paths.clone();
+    if let Some(manifest) = &options.manifest {
+        match read_manifest(manifest) {
+            Ok(paths) => entries.extend(paths),
+            Err(e) => {
+                eprintln!("error reading manifest {}: {}", manifest.display(), e);
+                exit(1);
+            }
+        }
+    }
+
+    // a pattern that matches nothing fails its own row only
+    let mut rows = Vec::new();
+    let mut files = Vec::new();
+    for entry in entries {
+        match config::expand_path(&entry) {
+            Ok(paths) => {
+                for path in paths {
+                    files.push((rows.len(), path.clone()));
+                    rows.push(Row {
+                        path: path.display().to_string(),
+                        ..Default::default()
+                    });
+                }
+            }
+            Err(e) => rows.push(Row {
+                path: entry.display().to_string(),
+                error: Some(e.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    let total_size = files
+        .iter()
+        .filter_map(|(_, path)| fs::metadata(path).ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum();
+    let progress = MultiProgress::new();
+    let progress_bar = progress.add(upload_progress_bar(Some(total_size)));
+    progress_bar.println(format!(
+        "uploading {} file(s) to {}/{} ...",
+        files.len(),
+        config.url,
+        config.bucket
+    ));
+    if config.resume {
+        progress_bar.println(
+            "warn: --resume does not apply to batch uploads, unfinished uploads are aborted",
+        );
+    }
+
+    // earlier uploads are listed once, and only checked for files of the same size
+    let candidates = match config.force_upload {
+        true => None,
+        false => match Candidates::list(config, bucket, client).await {
+            Ok(candidates) => Some(candidates),
+            Err(e) => {
+                progress_bar.println(format!("warn: could not look for earlier uploads: {}", e));
+                None
+            }
+        },
+    };
+
+    // the exit code of the signal that cancelled the batch
+    let (cancel_tx, cancel_rx) = watch::channel(None);
+    let signal = tokio::spawn(async move {
+        let code = upload::shutdown_signal().await;
+        let _ = cancel_tx.send(Some(code));
+    });
+
+    let config = Arc::new(config.clone());
+    let candidates = Arc::new(candidates);
+    let semaphore = Arc::new(Semaphore::new(options.jobs));
+    let mut tasks = JoinSet::new();
+    for (index, path) in files {
+        let config = config.clone();
+        let bucket = bucket.clone();
+        let client = client.clone();
+        let candidates = candidates.clone();
+        let semaphore = semaphore.clone();
+        let progress_bar = progress_bar.clone();
+        let cancel = cancel_rx.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+            if cancel.borrow().is_some() {
+                return (index, Err(UploadError::Cancelled));
+            }
+            let row = share(
+                &config,
+                &bucket,
+                &client,
+                candidates.as_ref().as_ref(),
+                &path,
+                &progress_bar,
+                cancel,
+            )
+            .await;
+            match &row {
+                Ok(_) => progress_bar.println(format!("uploaded {}", path.display())),
+                Err(e) => progress_bar.println(format!("failed {}: {}", path.display(), e)),
+            }
+            (index, row)
+        });
+    }
+    while let Some(task) = tasks.join_next().await {
+        let (index, row) = task.unwrap();
+        match row {
+            Ok(row) => rows[index] = row,
+            Err(e) => rows[index].error = Some(e.to_string()),
+        }
+    }
+    progress_bar.finish();
+    signal.abort();
+
+    let written = match &options.output {
+        Some(output) => {
+            fs::File::create(output).and_then(|file| write_table(&rows, options.format, file))
+        }
+        None => write_table(&rows, options.format, io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!("error writing table: {}", e);
+        exit(1);
+    }
+    if let Some(code) = *cancel_rx.borrow() {
+        eprintln!("upload cancelled");
+        exit(code);
+    }
+    let failed = rows.iter().filter(|row| row.error.is_some()).count();
+    if failed > 0 {
+        eprintln!("{} of {} file(s) failed", failed, rows.len());
+        exit(1);
+    }
+}
+
+/// Upload the file at `path` under a new key and create its link. If one of
+/// the `candidates` is a copy of the file, it is linked or copied instead. The upload
+/// stops once `cancel` is set.
+async fn share(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    candidates: Option<&Candidates>,
+    path: &Path,
+    progress_bar: &ProgressBar,
+    mut cancel: watch::Receiver<Option<i32>>,
+) -> Result<Row, UploadError> {
+    if path.is_dir() {
+        return Err(UploadError::Input(
+            "is a directory, batch mode only uploads files".to_string(),
+        ));
+    }
+    let file_name = path
+        .file_name()
+        .expect("A canonicalized path should have a file name")
+        .to_string_lossy()
+        .to_string();
+    let content_type = match &config.content_type {
+        Some(content_type) => content_type.clone(),
+        None => content_type::detect(&file_name, path).to_string(),
+    };
+
+    let mut file = fs::File::open(path).map_err(|e| UploadError::Input(e.to_string()))?;
+    let size = file
+        .metadata()
+        .map_err(|e| UploadError::Input(e.to_string()))?
+        .len();
+    // without an earlier upload of the same size, the digest is only computed while uploading
+    let candidates = candidates.filter(|c| c.has_size(size));
+    let sha256 = match candidates {
+        Some(_) => Some(
+            dedup::file_sha256(&file)
+                .await
+                .map_err(|e| UploadError::Input(e.to_string()))?,
+        ),
+        None => None,
+    };
+    let expiry = link::expiry(config);
+    let reuse = match (candidates, &sha256) {
+        (Some(candidates), Some(sha256)) => {
+            let found = candidates
+                .find(config, bucket, client, size, sha256)
+                .await?;
+            dedup::reuse(found, size, expiry)
+        }
+        _ => None,
+    };
+    let row = |key: String, valid_for: Duration, sha256: String| Row {
+        path: path.display().to_string(),
+        url: Some(link::share_link(
+            config,
+            bucket,
+            &key,
+            &file_name,
+            &content_type,
+            valid_for,
+        )),
+        key: Some(key),
+        size: Some(size),
+        expires: Some(humantime::format_rfc3339_seconds(SystemTime::now() + valid_for).to_string()),
+        sha256: Some(sha256),
+        error: None,
+    };
+    let copy_source = match reuse {
+        Some(Reuse::Link(key)) => {
+            progress_bar.inc(size);
+            let valid_for = link::valid_for(config, &key);
+            return Ok(row(
+                key,
+                valid_for,
+                sha256.expect("files are hashed before they are linked or copied again"),
+            ));
+        }
+        Some(Reuse::Copy(source)) => Some(source),
+        None => None,
+    };
+
+    let key = link::new_key(expiry, &file_name, config.public);
+    let mut headers: ObjectHeaders = vec![("content-type".to_string(), content_type.clone())];
+    headers.extend(sha256.as_deref().map(dedup::metadata));
+    headers.extend(link::object_headers(config, &file_name));
+    headers.extend(metadata::object_headers(
+        config,
+        &metadata::Recorded {
+            file_name: Some(&file_name),
+            size: Some(size),
+            expiry,
+        },
+    ));
+    if let Some(source) = copy_source {
+        let copy = upload::copy_object(config, bucket, client, &source, &key, &headers);
+        unless_cancelled(&mut cancel, copy).await?;
+        progress_bar.inc(size);
+        let valid_for = Duration::from_secs(config.link_expires.into());
+        return Ok(row(
+            key,
+            valid_for,
+            sha256.expect("files are hashed before they are linked or copied again"),
+        ));
+    }
+    let uploaded = if size > MULTIPART_LIMIT {
+        upload_multipart(
+            config,
+            bucket,
+            client,
+            &key,
+            &headers,
+            file,
+            size,
+            progress_bar,
+            &mut cancel,
+        )
+        .await?
+    } else {
+        let mut body = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut body)
+            .map_err(|e| UploadError::Input(e.to_string()))?;
+        if sha256.is_none() {
+            headers.push(dedup::metadata(&hex_digest(Sha256::new_with_prefix(&body))));
+        }
+        let put = upload::put_object(config, bucket, client, &key, &headers, body, progress_bar);
+        unless_cancelled(&mut cancel, put).await?
+    };
+    if config.verify {
+        verify::verify(config, bucket, client, &key, &uploaded).await?;
+    }
+    let valid_for = Duration::from_secs(config.link_expires.into());
+    Ok(row(key, valid_for, uploaded.sha256))
+}
+
+/// Upload a large file part by part. A failed or cancelled upload is aborted
+/// without ending the process, so the other files carry on.
+#[allow(clippy::too_many_arguments)]
+async fn upload_multipart(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    key: &str,
+    headers: &ObjectHeaders,
+    file: fs::File,
+    size: u64,
+    progress_bar: &ProgressBar,
+    cancel: &mut watch::Receiver<Option<i32>>,
+) -> Result<Uploaded, UploadError> {
+    let part_size = parts::part_size_for(size, config.part_size).map_err(UploadError::Input)?;
+    let upload = MultipartUpload::create(config, bucket, client, key, headers).await?;
+    let (upload_tx, upload_rx) = flume::bounded(config.concurrency);
+    let reader = spawn_part_reader(file, part_size, BTreeSet::new(), upload_tx);
+    let transfer = async {
+        let mut parts = upload
+            .upload_parts(upload_rx, config.concurrency, progress_bar, |_| {})
+            .await?;
+        let sha256 = reader
+            .await
+            .unwrap()
+            .map_err(|e| UploadError::Input(e.to_string()))?;
+        upload.complete(&mut parts, sha256).await
+    };
+    let uploaded = unless_cancelled(cancel, transfer).await;
+    if uploaded.is_err() {
+        if let Err(e) = upload.abort().await {
+            progress_bar.println(format!("warn: {}", e));
+        }
+    }
+    uploaded
+}
+
+/// Run `transfer` until it is done or `cancel` is set.
+async fn unless_cancelled<T>(
+    cancel: &mut watch::Receiver<Option<i32>>,
+    transfer: impl Future<Output = Result<T, UploadError>>,
+) -> Result<T, UploadError> {
+    tokio::select! {
+        result = transfer => result,
+        _ = cancel.wait_for(Option::is_some) => Err(UploadError::Cancelled),
+    }
+}
+
+/// Read the paths listed in `manifest`. Relative paths are relative to the
+/// directory of the manifest.
+fn read_manifest(manifest: &Path) -> Result<Vec<PathBuf>, csv::Error> {
+    let content = fs::read_to_string(manifest)?;
+    let is_csv = manifest
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    Ok(parse_manifest(&content, is_csv)?
+        .into_iter()
+        .map(|path| base.join(path))
+        .collect())
+}
+
+/// The paths in a manifest: one per line, or, for a CSV file, the first column
+/// of every row. Empty lines, lines starting with `#` and a `path` header are skipped.
+fn parse_manifest(content: &str, is_csv: bool) -> Result<Vec<String>, csv::Error> {
+    let lines: Vec<String> = if is_csv {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .from_reader(content.as_bytes());
+        let mut paths = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let path = record?.get(0).unwrap_or_default().trim().to_string();
+            if index == 0 && path.eq_ignore_ascii_case("path") {
+                continue;
+            }
+            paths.push(path);
+        }
+        paths
+    } else {
+        content
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.starts_with('#'))
+            .collect()
+    };
+    Ok(lines.into_iter().filter(|line| !line.is_empty()).collect())
+}
+
+/// Write `rows` to `writer` as CSV with a header, or as a JSON array.
+fn write_table<W: Write>(rows: &[Row], format: BatchFormat, mut writer: W) -> io::Result<()> {
+    match format {
+        BatchFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()
+        }
+        BatchFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, rows)?;
+            writeln!(writer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_manifest, write_table, Row};
+    use crate::config::BatchFormat;
+
+    #[test]
+    fn parses_plain_manifest() {
+        let manifest = "reports/a.pdf\n\n# skipped\n  reports/b, c.pdf  \r\n";
+        assert_eq!(
+            parse_manifest(manifest, false).unwrap(),
+            vec!["reports/a.pdf", "reports/b, c.pdf"]
+        );
+    }
+
+    #[test]
+    fn parses_csv_manifest() {
+        let manifest = "path,customer\nreports/a.pdf,ACME\n\"reports/b, c.pdf\",Initech\n\n";
+        assert_eq!(
+            parse_manifest(manifest, true).unwrap(),
+            vec!["reports/a.pdf", "reports/b, c.pdf"]
+        );
+        // without a header
+        assert_eq!(
+            parse_manifest("a.pdf\nb.pdf", true).unwrap(),
+            vec!["a.pdf", "b.pdf"]
+        );
+    }
+
+    #[test]
+    fn writes_failed_rows() {
+        let rows = [
+            Row {
+                path: "/tmp/a.pdf".to_string(),
+                key: Some("01ABC/a.pdf".to_string()),
+                url: Some("https://s3/bk/01ABC/a.pdf?X-Amz-Signature=1".to_string()),
+                size: Some(3),
+                expires: Some("2026-10-24T00:00:00Z".to_string()),
+                sha256: Some("abc".to_string()),
+                error: None,
+            },
+            Row {
+                path: "/tmp/b, c.pdf".to_string(),
+                error: Some("error reading input: No such file".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let mut csv = Vec::new();
+        write_table(&rows, BatchFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "path,key,url,size,expires,sha256,error\n\
+             /tmp/a.pdf,01ABC/a.pdf,https://s3/bk/01ABC/a.pdf?X-Amz-Signature=1,3,2026-10-24T00:00:00Z,abc,\n\
+             \"/tmp/b, c.pdf\",,,,,,error reading input: No such file\n"
+        );
+
+        let mut json = Vec::new();
+        write_table(&rows, BatchFormat::Json, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["size"], 3);
+        assert_eq!(json[1]["url"], serde_json::Value::Null);
+        assert_eq!(json[1]["error"], "error reading input: No such file");
+    }
+}
@@ -0,0 +1,125 @@
+// SHA-256 checksums, so the server rejects transfers that got corrupted on the way
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// Header carrying the checksum of a part or an object.
+pub const CHECKSUM_HEADER: &str = "x-amz-checksum-sha256";
+
+/// The SHA-256 of `data`, base64 encoded as S3 expects it in `x-amz-checksum-sha256`.
+pub fn checksum(data: &[u8]) -> String {
+    encode(&Sha256::digest(data))
+}
+
+/// Base64 encode a digest for `x-amz-checksum-sha256`.
+pub fn encode(digest: &[u8]) -> String {
+    BASE64_STANDARD.encode(digest)
+}
+
+/// Whether `checksum` is a base64 encoded SHA-256, e.g. one read back from a resume state.
+pub fn is_valid(checksum: &str) -> bool {
+    BASE64_STANDARD
+        .decode(checksum)
+        .is_ok_and(|digest| digest.len() == Sha256::output_size())
+}
+
+/// The checksum S3 reports for a multipart upload: the SHA-256 of the
+/// concatenated part checksums, followed by the number of parts.
+pub fn composite_checksum<'a>(
+    checksums: impl IntoIterator<Item = &'a str>,
+) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    let mut parts = 0;
+    for checksum in checksums {
+        let digest = BASE64_STANDARD.decode(checksum).map_err(|e| {
+            format!(
+                "invalid checksum \"{}\" of part {}: {}",
+                checksum,
+                parts + 1,
+                e
+            )
+        })?;
+        hasher.update(digest);
+        parts += 1;
+    }
+    Ok(format!("{}-{}", encode(&hasher.finalize()), parts))
+}
+
+/// The ETag S3 gives a multipart upload: the MD5 of the concatenated part
+/// MD5s, followed by the number of parts. `None` if a part ETag is not a plain
+/// MD5, e.g. for encrypted objects.
+pub fn multipart_etag<'a>(etags: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let mut hasher = Md5::new();
+    let mut parts = 0;
+    for etag in etags {
+        let etag = etag.trim_matches('"');
+        if etag.len() != 32 {
+            return None;
+        }
+        for i in (0..etag.len()).step_by(2) {
+            hasher.update([u8::from_str_radix(etag.get(i..i + 2)?, 16).ok()?]);
+        }
+        parts += 1;
+    }
+    Some(format!("\"{:x}-{}\"", hasher.finalize(), parts))
+}
+
+/// The hex encoded digest of everything fed into `hasher`, as printed by `sha256sum`.
+pub fn hex_digest(hasher: Sha256) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::{checksum, composite_checksum, hex_digest, is_valid, multipart_etag};
+
+    #[test]
+    fn encodes_like_s3_and_sha256sum() {
+        assert_eq!(
+            checksum(b"hello"),
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+        assert_eq!(
+            hex_digest(Sha256::new_with_prefix(b"hello")),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn composite_checksum_hashes_part_checksums() {
+        let parts = [checksum(b"hello"), checksum(b"world")];
+        let mut digests = Sha256::digest(b"hello").to_vec();
+        digests.extend(Sha256::digest(b"world"));
+
+        assert_eq!(
+            composite_checksum(parts.iter().map(|c| c.as_str())),
+            Ok(format!("{}-2", checksum(&digests)))
+        );
+        assert!(composite_checksum(["not base64!"]).is_err());
+    }
+
+    #[test]
+    fn only_sha256_digests_are_valid() {
+        assert!(is_valid(&checksum(b"hello")));
+        assert!(!is_valid("c2hh"));
+        assert!(!is_valid("not base64!"));
+    }
+
+    #[test]
+    fn multipart_etag_hashes_part_md5s() {
+        // md5("hello") and md5("world")
+        let etags = [
+            "\"5d41402abc4b2a76b9719d911017c592\"",
+            "\"7d793037a0760186574b0282f2f435e7\"",
+        ];
+
+        assert_eq!(
+            multipart_etag(etags).unwrap(),
+            "\"065947336a2f2a95ba8899f3675c3be6-2\""
+        );
+        assert_eq!(multipart_etag(["\"not-an-md5\""]), None);
+    }
+}
@@ -1,61 +1,230 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{CommandFactory, Parser};
+use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::Shell;
 use rusty_s3::Credentials;
 
-use super::{CompressionMthd, PartialConfig};
+use crate::metadata;
+
+use super::{
+    BatchFormat, BatchOptions, Command, CompressionMthd, DecryptOptions, DeleteOptions,
+    ExtendOptions, ListFormat, ListOptions, ListSort, PartialConfig, RelinkOptions, STDIN_PATH,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version)]
 pub(crate) struct Args {
-    /// How long the link should be valid for (default: 7d)
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+
+    /// How long the upload is kept, until --purge deletes it (default: 7d)
+    #[arg(short, long, global = true)]
     expires: Option<String>,
 
+    /// How long the link is valid for, at most 7d (default: as long as the upload is kept, up to 7d)
+    #[arg(long, global = true)]
+    link_expires: Option<String>,
+
     /// Which bucket to upload to
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     bucket: Option<String>,
 
     /// What URL to use
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     url: Option<String>,
 
     /// Which region to use (default: eu-central-1)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     region: Option<String>,
 
     /// S3 access key
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     access_key: Option<String>,
 
     /// S3 secret key
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     secret_key: Option<String>,
 
     /// How to compress into zip file (default: deflate)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     compression: Option<CompressionMthd>,
 
     /// Whether to zip a single file
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     zip_single_file: bool,
 
     /// Whether to purge expired files before uploading
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     purge: bool,
 
     /// Print a QR code for the generated share URL
-    #[arg(long)]
+    #[arg(long, global = true)]
     qr: bool,
 
+    /// How often a failed part is retried before giving up (default: 5)
+    #[arg(long, global = true)]
+    retries: Option<u32>,
+
+    /// Record the progress of large uploads next to the file and continue an
+    /// interrupted upload of the same file
+    #[arg(long, global = true)]
+    resume: bool,
+
+    /// Size of the parts of large uploads, e.g. 64MiB (default: picked from the file size)
+    #[arg(long, global = true)]
+    part_size: Option<String>,
+
+    /// How many parts are uploaded at the same time (default: 8)
+    #[arg(long, global = true)]
+    concurrency: Option<usize>,
+
+    /// Check the uploaded object's size and checksum with a HEAD request
+    /// before printing the link
+    #[arg(long, global = true)]
+    verify: bool,
+
+    /// Content type of the uploaded file (default: guessed from the file name and content)
+    #[arg(long, global = true)]
+    content_type: Option<String>,
+
+    /// File name for downloads, instead of the name of the uploaded path
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Let browsers show the file instead of downloading it
+    #[arg(long, global = true)]
+    inline: bool,
+
+    /// Upload the file even if an unexpired copy of it is already in the bucket
+    #[arg(long, global = true)]
+    force_upload: bool,
+
+    /// Server-side encryption: aes256, kms, kms:<key-id> or customer (SSE-C)
+    #[arg(long, global = true)]
+    sse: Option<String>,
+
+    /// Base64 encoded 256 bit key for --sse customer
+    #[arg(long, global = true)]
+    sse_customer_key: Option<String>,
+
+    /// Encrypt the upload before it leaves this machine. The key is only in
+    /// the link, after the `#`. Decrypt it with `crab_share decrypt <link>`
+    #[arg(long, global = true)]
+    encrypt: bool,
+
+    /// Storage class of the upload, e.g. STANDARD_IA or ONEZONE_IA (default: the one of the bucket)
+    #[arg(long, global = true)]
+    storage_class: Option<String>,
+
+    /// Store `key=value` as metadata with the upload, can be given several times
+    #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = metadata::split_meta, global = true)]
+    meta: Vec<(String, String)>,
+
+    /// Do not store the file name, size, hostname and expiry with the upload
+    #[arg(long, global = true)]
+    no_default_meta: bool,
+
+    /// How the bucket is addressed in requests and links: path, virtual or
+    /// auto, which picks virtual-hosted style for AWS (default: auto)
+    #[arg(long, global = true)]
+    url_style: Option<String>,
+
+    /// Print a plain link below publicBaseUrl instead of a presigned one. The
+    /// key gets a random part, so links can not be guessed
+    #[arg(long, global = true)]
+    public: bool,
+
+    /// Base URL of public links, like a CDN or a public-read bucket
+    #[arg(long, global = true)]
+    public_base_url: Option<String>,
+
     /// Generate shell completion script for the specified shell
     #[arg(long, value_name = "SHELL")]
     generate_completion: Option<Shell>,
 
-    /// Path to upload. If it is a directory, it will be zipped.
+    /// Upload what is read from stdin, same as passing `-` as path
+    #[arg(long, conflicts_with = "paths")]
+    stdin: bool,
+
+    /// Paths or glob patterns to upload. A directory is zipped, several paths
+    /// are zipped into one archive. `-` reads stdin.
     #[arg()]
-    path: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Upload every path on its own and write a table with one link per file
+    Batch {
+        /// File with one path per line, or a CSV file (*.csv) with the path in
+        /// its first column
+        #[arg(short, long)]
+        manifest: Option<PathBuf>,
+
+        /// Format of the table
+        #[arg(long, value_enum, default_value_t = BatchFormat::Csv)]
+        format: BatchFormat,
+
+        /// Write the table to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// How many files are uploaded at the same time (default: 4)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Paths or glob patterns to upload, each file gets its own link
+        #[arg()]
+        paths: Vec<PathBuf>,
+    },
+    /// Show the shares in the bucket with their size and remaining lifetime
+    List {
+        /// Order of the shares
+        #[arg(long, value_enum, default_value_t = ListSort::Expiry)]
+        sort: ListSort,
+
+        /// Only show shares that expire within this time, e.g. 24h or 3d
+        #[arg(long, value_parser = humantime::parse_duration)]
+        expiring_within: Option<Duration>,
+
+        /// Show expired shares as well
+        #[arg(long)]
+        all: bool,
+
+        /// Format of the list
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Delete shares, so that their links stop working
+    Delete {
+        /// Links, ULIDs or keys of the shares
+        #[arg(required = true)]
+        shares: Vec<String>,
+
+        /// Delete without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Keep a share for longer (or shorter): it expires after --expires from now
+    Extend {
+        /// Link or key of the share
+        share: String,
+    },
+    /// Print a new link to a share whose link expired, or to any other object
+    Relink {
+        /// Earlier link or key of the object
+        share: String,
+    },
+    /// Download and decrypt a share that was uploaded with --encrypt
+    Decrypt {
+        /// The link, with the key after the `#`
+        url: String,
+
+        /// Where to write the file (default: its original name in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 impl Args {
@@ -67,6 +236,46 @@ impl Args {
             std::process::exit(0);
         }
     }
+
+    /// Take the subcommand out of the arguments. Options that apply to all
+    /// commands stay behind.
+    pub fn take_command(&mut self) -> Command {
+        match self.command.take() {
+            None => Command::Upload,
+            Some(Subcommands::Batch {
+                manifest,
+                format,
+                output,
+                jobs,
+                paths,
+            }) => Command::Batch(BatchOptions {
+                manifest,
+                format,
+                output,
+                jobs: jobs.unwrap_or(4),
+                paths,
+            }),
+            Some(Subcommands::List {
+                sort,
+                expiring_within,
+                all,
+                format,
+            }) => Command::List(ListOptions {
+                sort,
+                expiring_within,
+                all,
+                format,
+            }),
+            Some(Subcommands::Delete { shares, yes }) => {
+                Command::Delete(DeleteOptions { shares, yes })
+            }
+            Some(Subcommands::Extend { share }) => Command::Extend(ExtendOptions { share }),
+            Some(Subcommands::Relink { share }) => Command::Relink(RelinkOptions { share }),
+            Some(Subcommands::Decrypt { url, output }) => {
+                Command::Decrypt(DecryptOptions { url, output })
+            }
+        }
+    }
 }
 
 impl From<Args> for PartialConfig {
@@ -82,17 +291,57 @@ impl From<Args> for PartialConfig {
         };
         let purge = if args.purge { Some(true) } else { None };
         let qr = if args.qr { Some(true) } else { None };
+        let resume = if args.resume { Some(true) } else { None };
+        let verify = if args.verify { Some(true) } else { None };
+        let inline = if args.inline { Some(true) } else { None };
+        let force_upload = if args.force_upload { Some(true) } else { None };
+        let encrypt = if args.encrypt { Some(true) } else { None };
+        let public = if args.public { Some(true) } else { None };
+        let default_meta = if args.no_default_meta {
+            Some(false)
+        } else {
+            None
+        };
         PartialConfig {
             expires: args.expires,
             bucket: args.bucket,
             url: args.url,
-            path: args.path,
+            paths: if args.stdin {
+                Some(vec![PathBuf::from(STDIN_PATH)])
+            } else if args.paths.is_empty() {
+                None
+            } else {
+                Some(args.paths)
+            },
             region: args.region,
             credentials,
             compression: args.compression.map(|mthd| mthd.into()),
             zip_single_file,
             purge,
             qr,
+            retries: args.retries.map(|n| n.to_string()),
+            resume,
+            part_size: args.part_size,
+            concurrency: args.concurrency.map(|n| n.to_string()),
+            verify,
+            content_type: args.content_type,
+            name: args.name,
+            inline,
+            force_upload,
+            sse: args.sse,
+            sse_customer_key: args.sse_customer_key,
+            encrypt,
+            storage_class: args.storage_class,
+            meta: if args.meta.is_empty() {
+                None
+            } else {
+                Some(args.meta)
+            },
+            default_meta,
+            url_style: args.url_style,
+            public,
+            public_base_url: args.public_base_url,
+            link_expires: args.link_expires,
         }
     }
 }
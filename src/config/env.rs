@@ -9,7 +9,7 @@ pub(crate) struct EnvConf {
     access_key: Option<String>,
     secret_key: Option<String>,
 
-    /// How long the link should be valid for in seconds (default: 7d)
+    /// How long uploads are kept (default: 7d)
     expires: Option<String>,
     /// Which bucket to upload to
     bucket: Option<String>,
@@ -24,6 +24,30 @@ pub(crate) struct EnvConf {
     zip_single_file: Option<bool>,
     /// Whether to purge expired files before uploading
     purge: Option<bool>,
+    /// How often a failed part is retried before giving up
+    retries: Option<String>,
+    /// Size of the parts of large uploads
+    part_size: Option<String>,
+    /// How many parts are uploaded at the same time
+    concurrency: Option<String>,
+    /// Whether to check the uploaded object before printing the link
+    verify: Option<bool>,
+    /// Server-side encryption of uploads
+    sse: Option<String>,
+    /// Base64 encoded 256 bit key for SSE-C
+    sse_customer_key: Option<String>,
+    /// Whether to encrypt uploads end-to-end
+    encrypt: Option<bool>,
+    /// Storage class of uploads
+    storage_class: Option<String>,
+    /// How the bucket is addressed: path, virtual or auto
+    url_style: Option<String>,
+    /// Whether to print public links
+    public: Option<bool>,
+    /// Base URL of public links
+    public_base_url: Option<String>,
+    /// How long links are valid for
+    link_expires: Option<String>,
 }
 
 impl TryInto<Credentials> for EnvConf {
@@ -51,12 +75,31 @@ impl From<EnvConf> for PartialConfig {
             url: json_credentials.url,
             expires: json_credentials.expires,
             bucket: json_credentials.bucket,
-            path: json_credentials.path,
+            paths: json_credentials.path.map(|p| vec![p]),
             region: json_credentials.region,
             compression: json_credentials.compression.map(|c| c.into()),
             zip_single_file: json_credentials.zip_single_file,
             purge: json_credentials.purge,
             qr: None,
+            retries: json_credentials.retries,
+            resume: None,
+            part_size: json_credentials.part_size,
+            concurrency: json_credentials.concurrency,
+            verify: json_credentials.verify,
+            content_type: None,
+            name: None,
+            inline: None,
+            force_upload: None,
+            sse: json_credentials.sse,
+            sse_customer_key: json_credentials.sse_customer_key,
+            encrypt: json_credentials.encrypt,
+            storage_class: json_credentials.storage_class,
+            meta: None,
+            default_meta: None,
+            url_style: json_credentials.url_style,
+            public: json_credentials.public,
+            public_base_url: json_credentials.public_base_url,
+            link_expires: json_credentials.link_expires,
         }
     }
 }
@@ -75,6 +118,18 @@ impl EnvConf {
         let compression = env::var("S3_COMPRESSION").ok().map(|c| c.into());
         let zip_single_file = env::var("S3_ZIP_SINGLE_FILE").ok().map(|_| true);
         let purge = env::var("S3_PURGE").ok().map(|_| true);
+        let retries = env::var("S3_RETRIES").ok();
+        let part_size = env::var("S3_PART_SIZE").ok();
+        let concurrency = env::var("S3_CONCURRENCY").ok();
+        let verify = env::var("S3_VERIFY").ok().map(|_| true);
+        let sse = env::var("S3_SSE").ok();
+        let sse_customer_key = env::var("S3_SSE_CUSTOMER_KEY").ok();
+        let encrypt = env::var("S3_ENCRYPT").ok().map(|_| true);
+        let storage_class = env::var("S3_STORAGE_CLASS").ok();
+        let url_style = env::var("S3_URL_STYLE").ok();
+        let public = env::var("S3_PUBLIC").ok().map(|_| true);
+        let public_base_url = env::var("S3_PUBLIC_BASE_URL").ok();
+        let link_expires = env::var("S3_LINK_EXPIRES").ok();
         EnvConf {
             url,
             access_key,
@@ -86,6 +141,18 @@ impl EnvConf {
             compression,
             zip_single_file,
             purge,
+            retries,
+            part_size,
+            concurrency,
+            verify,
+            sse,
+            sse_customer_key,
+            encrypt,
+            storage_class,
+            url_style,
+            public,
+            public_base_url,
+            link_expires,
         }
     }
 }
@@ -33,7 +33,7 @@ impl From<std::io::Error> for ConfigError {
 
 impl From<env::VarError> for ConfigError {
     fn from(e: env::VarError) -> Self {
-        ConfigError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
+        ConfigError::Io(std::io::Error::other(e))
     }
 }
 
@@ -1,4 +1,4 @@
-use std::{env, fs, path::Path};
+use std::{collections::BTreeMap, env, fs, path::Path};
 
 use rusty_s3::Credentials;
 use serde::Deserialize;
@@ -12,6 +12,8 @@ pub(crate) struct JSONCredentials {
     access_key: Option<String>,
     #[serde(rename = "secretKey")]
     secret_key: Option<String>,
+    /// Whether to use path-style URLs: `on`, `off` or `auto`, like `mc` configs
+    path: Option<String>,
 }
 
 impl TryInto<Credentials> for JSONCredentials {
@@ -37,6 +39,7 @@ impl From<JSONCredentials> for PartialConfig {
         PartialConfig {
             url: json_credentials.url,
             credentials,
+            url_style: json_credentials.path,
             ..PartialConfig::default()
         }
     }
@@ -63,6 +66,27 @@ pub(crate) struct JSONConfig {
     #[serde(rename = "zipSingleFile")]
     zip_single_file: Option<bool>,
     purge: Option<bool>,
+    retries: Option<u32>,
+    #[serde(rename = "partSize")]
+    part_size: Option<String>,
+    concurrency: Option<usize>,
+    verify: Option<bool>,
+    sse: Option<String>,
+    #[serde(rename = "sseCustomerKey")]
+    sse_customer_key: Option<String>,
+    encrypt: Option<bool>,
+    #[serde(rename = "storageClass")]
+    storage_class: Option<String>,
+    metadata: Option<BTreeMap<String, String>>,
+    #[serde(rename = "defaultMeta")]
+    default_meta: Option<bool>,
+    #[serde(rename = "urlStyle")]
+    url_style: Option<String>,
+    public: Option<bool>,
+    #[serde(rename = "publicBaseUrl")]
+    public_base_url: Option<String>,
+    #[serde(rename = "linkExpires")]
+    link_expires: Option<String>,
 }
 
 impl From<JSONConfig> for PartialConfig {
@@ -71,13 +95,34 @@ impl From<JSONConfig> for PartialConfig {
             bucket: json_config.bucket,
             region: json_config.region,
             url: json_config.url,
-            path: None,
+            paths: None,
             credentials: None,
             expires: json_config.expires,
             compression: json_config.compression.map(|c| c.into()),
             zip_single_file: json_config.zip_single_file,
             purge: json_config.purge,
             qr: None,
+            retries: json_config.retries.map(|n| n.to_string()),
+            resume: None,
+            part_size: json_config.part_size,
+            concurrency: json_config.concurrency.map(|n| n.to_string()),
+            verify: json_config.verify,
+            content_type: None,
+            name: None,
+            inline: None,
+            force_upload: None,
+            sse: json_config.sse,
+            sse_customer_key: json_config.sse_customer_key,
+            encrypt: json_config.encrypt,
+            storage_class: json_config.storage_class,
+            meta: json_config
+                .metadata
+                .map(|metadata| metadata.into_iter().collect()),
+            default_meta: json_config.default_meta,
+            url_style: json_config.url_style,
+            public: json_config.public,
+            public_base_url: json_config.public_base_url,
+            link_expires: json_config.link_expires,
         }
     }
 }
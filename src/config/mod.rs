@@ -10,13 +10,25 @@ use self::args::*;
 mod env;
 use self::env::*;
 
-use std::path::PathBuf;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use clap::Parser;
-use rusty_s3::Credentials;
+use bytesize::ByteSize;
+use clap::{Parser, ValueEnum};
+use reqwest::Url;
+use rusty_s3::{Credentials, UrlStyle};
 use serde::Deserialize;
 use zip::CompressionMethod;
 
+use crate::link;
+use crate::metadata;
+use crate::sse::Sse;
+
+/// The path that stands for stdin.
+pub const STDIN_PATH: &str = "-";
+
 #[derive(Debug, Deserialize, Clone)]
 pub enum CompressionMthd {
     #[serde(rename = "deflate")]
@@ -52,16 +64,124 @@ impl From<String> for CompressionMthd {
     }
 }
 
+/// What to do with the paths.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Upload all paths as one object and print its link
+    Upload,
+    /// Upload every file on its own and write a table of links
+    Batch(BatchOptions),
+    /// Download and decrypt an encrypted share
+    Decrypt(DecryptOptions),
+    /// Show the shares in the bucket
+    List(ListOptions),
+    /// Delete shares, so their links stop working
+    Delete(DeleteOptions),
+    /// Move a share to a key with a new expiry
+    Extend(ExtendOptions),
+    /// Print a new link to an object
+    Relink(RelinkOptions),
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// File with the paths to upload, one per line or in the first column of a CSV file
+    pub manifest: Option<PathBuf>,
+    /// Format of the table of links
+    pub format: BatchFormat,
+    /// Where to write the table of links (default: stdout)
+    pub output: Option<PathBuf>,
+    /// How many files are uploaded at the same time (default: 4)
+    pub jobs: usize,
+    /// Paths or glob patterns to upload, in addition to the ones in the manifest
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecryptOptions {
+    /// Link to the share, with the key in its fragment
+    pub url: String,
+    /// Where to write the decrypted file (default: its original name)
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum BatchFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Order of the shares
+    pub sort: ListSort,
+    /// Only show shares that expire within this time
+    pub expiring_within: Option<Duration>,
+    /// Whether to show expired shares as well
+    pub all: bool,
+    /// Format of the list
+    pub format: ListFormat,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteOptions {
+    /// Links, ULIDs or keys of the shares to delete
+    pub shares: Vec<String>,
+    /// Whether to delete without asking first
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtendOptions {
+    /// Link or key of the share
+    pub share: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelinkOptions {
+    /// Link or key of the object
+    pub share: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ListSort {
+    /// The share that expires first comes first
+    Expiry,
+    /// The largest share comes first
+    Size,
+    /// By key, which is the order of upload for shares with the same lifetime
+    Key,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ListFormat {
+    Table,
+    Json,
+}
+
+/// What the command line asks for.
+#[derive(Debug)]
+pub enum Parsed {
+    /// Decrypting a share needs no bucket, so it needs no configuration
+    Decrypt(DecryptOptions),
+    /// Everything else
+    Config(Box<Config>),
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// How long the link should be valid for in seconds (default: 7d)
+    /// What to do
+    pub command: Command,
+    /// How long uploads are kept in seconds, which is in the ULID of their key (default: 7d)
     pub expires: u32,
     /// Which bucket to upload to
     pub bucket: String,
     /// What URL to use
     pub url: String,
-    /// Path to upload. If it is a directory, it will be zipped.
-    pub path: PathBuf,
+    /// Paths to upload. A directory is zipped, several paths are zipped into one archive.
+    pub paths: Vec<PathBuf>,
+    /// Whether to upload what is read from stdin instead of `paths`
+    pub stdin: bool,
     /// Aws credentials
     pub credentials: Credentials,
     /// Aws region (default: eu-central-1)
@@ -74,6 +194,42 @@ pub struct Config {
     pub purge: bool,
     /// Whether to print a QR code for the generated URL
     pub qr: bool,
+    /// How often a failed part is retried before giving up (default: 5)
+    pub retries: u32,
+    /// Whether to keep a resume state for multipart uploads and continue an interrupted one
+    pub resume: bool,
+    /// Size of the parts of a multipart upload in bytes (default: picked from the input size)
+    pub part_size: Option<u64>,
+    /// How many parts are uploaded at the same time (default: 8)
+    pub concurrency: usize,
+    /// Whether to check the uploaded object with a HEAD request before printing the link
+    pub verify: bool,
+    /// Content type of the uploaded object (default: guessed from the file name and content)
+    pub content_type: Option<String>,
+    /// File name of the upload in its key and for downloads (default: the name of the path)
+    pub name: Option<String>,
+    /// Whether browsers should show the file instead of downloading it
+    pub inline: bool,
+    /// Whether to upload even if the same file was uploaded before
+    pub force_upload: bool,
+    /// Server-side encryption of uploads
+    pub sse: Option<Sse>,
+    /// Whether to encrypt uploads with a key that is only in the link
+    pub encrypt: bool,
+    /// Storage class of uploads (default: the one of the bucket)
+    pub storage_class: Option<String>,
+    /// Metadata stored with uploads, in addition to the default metadata
+    pub meta: Vec<(String, String)>,
+    /// Whether to store the file name, size, hostname and expiry with uploads
+    pub default_meta: bool,
+    /// How the bucket is addressed: path, virtual or auto (default: auto)
+    pub url_style: UrlStyle,
+    /// Whether to print a link below `public_base_url` instead of a presigned one
+    pub public: bool,
+    /// Base URL of public links, like a CDN in front of the bucket
+    pub public_base_url: Option<String>,
+    /// How long links are valid for in seconds, at most 7 days (default: `expires`, up to 7 days)
+    pub link_expires: u32,
 }
 
 /// Partial config: All possible config options, all optional. To be merged with other configs.
@@ -85,8 +241,8 @@ struct PartialConfig {
     bucket: Option<String>,
     /// What URL to use
     url: Option<String>,
-    /// Path to upload. If it is a directory, it will be zipped.
-    path: Option<PathBuf>,
+    /// Paths or glob patterns to upload. A directory is zipped, several paths are zipped into one archive.
+    paths: Option<Vec<PathBuf>>,
     /// The region to use (default: eu-central-1)
     region: Option<String>,
     /// Aws credentials
@@ -99,6 +255,44 @@ struct PartialConfig {
     purge: Option<bool>,
     /// Whether to print a QR code for the generated URL
     qr: Option<bool>,
+    /// How often a failed part is retried before giving up (default: 5)
+    retries: Option<String>,
+    /// Whether to keep a resume state for multipart uploads and continue an interrupted one
+    resume: Option<bool>,
+    /// Size of the parts of a multipart upload, e.g. 64MiB (default: picked from the input size)
+    part_size: Option<String>,
+    /// How many parts are uploaded at the same time (default: 8)
+    concurrency: Option<String>,
+    /// Whether to check the uploaded object with a HEAD request before printing the link
+    verify: Option<bool>,
+    /// Content type of the uploaded object (default: guessed from the file name and content)
+    content_type: Option<String>,
+    /// File name of the upload in its key and for downloads (default: the name of the path)
+    name: Option<String>,
+    /// Whether browsers should show the file instead of downloading it
+    inline: Option<bool>,
+    /// Whether to upload even if the same file was uploaded before
+    force_upload: Option<bool>,
+    /// Server-side encryption of uploads: aes256, kms, kms:<key-id> or customer
+    sse: Option<String>,
+    /// Base64 encoded 256 bit key for SSE-C
+    sse_customer_key: Option<String>,
+    /// Whether to encrypt uploads with a key that is only in the link
+    encrypt: Option<bool>,
+    /// Storage class of uploads (default: the one of the bucket)
+    storage_class: Option<String>,
+    /// Metadata stored with uploads, in addition to the default metadata
+    meta: Option<Vec<(String, String)>>,
+    /// Whether to store the file name, size, hostname and expiry with uploads
+    default_meta: Option<bool>,
+    /// How the bucket is addressed: path, virtual or auto (default: auto)
+    url_style: Option<String>,
+    /// Whether to print a link below `public_base_url` instead of a presigned one
+    public: Option<bool>,
+    /// Base URL of public links, like a CDN in front of the bucket
+    public_base_url: Option<String>,
+    /// How long links are valid for in seconds, at most 7 days (default: `expires`, up to 7 days)
+    link_expires: Option<String>,
 }
 
 impl PartialConfig {
@@ -107,13 +301,32 @@ impl PartialConfig {
             expires: self.expires.or(other.expires),
             bucket: self.bucket.or(other.bucket),
             url: self.url.or(other.url),
-            path: self.path.or(other.path),
+            paths: self.paths.or(other.paths),
             region: self.region.or(other.region),
             credentials: self.credentials.or(other.credentials),
             compression: self.compression.or(other.compression),
             zip_single_file: self.zip_single_file.or(other.zip_single_file),
             purge: self.purge.or(other.purge),
             qr: self.qr.or(other.qr),
+            retries: self.retries.or(other.retries),
+            resume: self.resume.or(other.resume),
+            part_size: self.part_size.or(other.part_size),
+            concurrency: self.concurrency.or(other.concurrency),
+            verify: self.verify.or(other.verify),
+            content_type: self.content_type.or(other.content_type),
+            name: self.name.or(other.name),
+            inline: self.inline.or(other.inline),
+            force_upload: self.force_upload.or(other.force_upload),
+            sse: self.sse.or(other.sse),
+            sse_customer_key: self.sse_customer_key.or(other.sse_customer_key),
+            encrypt: self.encrypt.or(other.encrypt),
+            storage_class: self.storage_class.or(other.storage_class),
+            meta: merge_meta(self.meta, other.meta),
+            default_meta: self.default_meta.or(other.default_meta),
+            url_style: self.url_style.or(other.url_style),
+            public: self.public.or(other.public),
+            public_base_url: self.public_base_url.or(other.public_base_url),
+            link_expires: self.link_expires.or(other.link_expires),
         }
     }
 
@@ -122,23 +335,46 @@ impl PartialConfig {
             expires: Some("7d".to_string()),
             bucket: None,
             url: None,
-            path: None,
+            paths: None,
             region: Some("eu-central-1".to_string()),
             credentials: None,
             compression: Some(CompressionMethod::Deflated),
             zip_single_file: Some(false),
             purge: Some(false),
             qr: Some(false),
+            retries: Some("5".to_string()),
+            resume: Some(false),
+            part_size: None,
+            concurrency: Some("8".to_string()),
+            verify: Some(false),
+            content_type: None,
+            name: None,
+            inline: Some(false),
+            force_upload: Some(false),
+            sse: None,
+            sse_customer_key: None,
+            encrypt: Some(false),
+            storage_class: None,
+            meta: None,
+            default_meta: Some(true),
+            url_style: Some("auto".to_string()),
+            public: Some(false),
+            public_base_url: None,
+            link_expires: None,
         }
     }
 }
 
 impl Config {
-    pub fn parse() -> Result<Self, ConfigError> {
-        let args = Args::parse();
+    pub fn parse() -> Result<Parsed, ConfigError> {
+        let mut args = Args::parse();
 
         // Handle completion generation first (exits if requested)
         args.generate_completion_if_requested();
+        let command = args.take_command();
+        if let Command::Decrypt(options) = command {
+            return Ok(Parsed::Decrypt(options));
+        }
 
         let args_config = PartialConfig::from(args);
 
@@ -150,7 +386,7 @@ impl Config {
         let partial_config = if let Ok(json_config) = json_config {
             partial_config.merge(PartialConfig::from(json_config))
         } else {
-            println!(
+            eprintln!(
                 "Warn: Could not read ~/.aws/crab_share.json: {}",
                 json_config.unwrap_err()
             );
@@ -162,7 +398,7 @@ impl Config {
         let partial_config = if let Ok(partial_config_creds) = partial_config_creds {
             partial_config.merge(partial_config_creds)
         } else {
-            println!(
+            eprintln!(
                 "Warn: Could not read ~/.aws/credentials.json: {}",
                 partial_config_creds.unwrap_err()
             );
@@ -172,28 +408,150 @@ impl Config {
         // fill the rest with the static defaults
         let partial_config = partial_config.merge(PartialConfig::static_default());
 
-        if let Some(path) = &partial_config.path {
-            if !path.exists() {
+        // batch uploads expand their paths one by one, so that a missing
+        // file fails its own row only
+        let paths = match (&command, &partial_config.paths) {
+            (Command::Batch(batch), _) => {
+                if batch.paths.is_empty() && batch.manifest.is_none() {
+                    return Err(ConfigError::Parse("No path or manifest given".to_string()));
+                }
+                if batch.paths.iter().any(|p| p.as_os_str() == STDIN_PATH) {
+                    return Err(ConfigError::Parse(
+                        "stdin can not be uploaded in batch mode".to_string(),
+                    ));
+                }
+                if batch.jobs == 0 {
+                    return Err(ConfigError::Parse("jobs must be at least 1".to_string()));
+                }
+                Vec::new()
+            }
+            (Command::Upload, Some(paths)) => expand_paths(paths)?,
+            (Command::Upload, None) => return Err(ConfigError::Parse("No path given".to_string())),
+            (
+                Command::List(_) | Command::Delete(_) | Command::Extend(_) | Command::Relink(_),
+                _,
+            ) => Vec::new(),
+            (Command::Decrypt(_), _) => unreachable!("decrypt needs no configuration"),
+        };
+        let part_size = match &partial_config.part_size {
+            Some(part_size) => Some(
+                part_size
+                    .parse::<ByteSize>()
+                    .map_err(|e| {
+                        ConfigError::Parse(format!(
+                            "Could not parse part size \"{part_size}\": {e}"
+                        ))
+                    })?
+                    .as_u64(),
+            ),
+            None => None,
+        };
+        let retries = parse_number_setting(
+            "retries",
+            partial_config
+                .retries
+                .as_deref()
+                .expect("retries should always be set by static default"),
+        )?;
+        let concurrency = parse_number_setting(
+            "concurrency",
+            partial_config
+                .concurrency
+                .as_deref()
+                .expect("concurrency should always be set by static default"),
+        )?;
+        if concurrency == 0 {
+            return Err(ConfigError::Parse(
+                "concurrency must be at least 1".to_string(),
+            ));
+        }
+        if let Some(name) = &partial_config.name {
+            if name.is_empty() || name.contains('/') {
                 return Err(ConfigError::Parse(format!(
-                    "Path {} does not exist",
-                    path.display()
+                    "Invalid name \"{name}\": must not be empty or contain '/'"
                 )));
             }
-        } else {
-            return Err(ConfigError::Parse("No path given".to_string()));
         }
-        Ok(Config {
-            expires: partial_config
-                .expires
+        let sse = match &partial_config.sse {
+            Some(sse) => Some(
+                Sse::parse(sse, partial_config.sse_customer_key.as_deref())
+                    .map_err(ConfigError::Parse)?,
+            ),
+            None => None,
+        };
+        let encrypt = partial_config
+            .encrypt
+            .expect("encrypt should always be set by static default");
+        if encrypt && matches!(command, Command::Batch(_)) {
+            return Err(ConfigError::Parse(
+                "--encrypt is not supported in batch mode".to_string(),
+            ));
+        }
+        // encrypted uploads are streamed like archives, they can not be resumed
+        if encrypt && partial_config.resume == Some(true) {
+            return Err(ConfigError::Parse(
+                "--resume can not be used together with --encrypt".to_string(),
+            ));
+        }
+        let storage_class = match &partial_config.storage_class {
+            Some(storage_class) => {
+                Some(metadata::parse_storage_class(storage_class).map_err(ConfigError::Parse)?)
+            }
+            None => None,
+        };
+        let meta = partial_config
+            .meta
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(metadata::check_meta)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ConfigError::Parse)?;
+        let public = partial_config
+            .public
+            .expect("public should always be set by static default");
+        let public_base_url = match &partial_config.public_base_url {
+            Some(base_url) => {
+                Url::parse(base_url).map_err(|e| {
+                    ConfigError::Parse(format!("Could not parse public base url: {e}"))
+                })?;
+                Some(base_url.trim_end_matches('/').to_string())
+            }
+            None => None,
+        };
+        if public && public_base_url.is_none() {
+            return Err(ConfigError::Missing(
+                "publicBaseUrl, which --public needs".to_string(),
+            ));
+        }
+        // public links are not signed, so they can not carry the SSE-C key
+        if public && matches!(sse, Some(Sse::Customer(_))) {
+            return Err(ConfigError::Parse(
+                "--public can not be used together with --sse customer".to_string(),
+            ));
+        }
+        let url_style = parse_url_style(
+            partial_config
+                .url_style
                 .as_deref()
-                .map(get_time_from_str)
-                .expect("expires should always be set by static default")
-                .ok_or_else(move || {
-                    ConfigError::Parse(format!(
-                        "Could not parse expires: \"{}\"",
-                        partial_config.expires.unwrap()
-                    ))
-                })?,
+                .expect("url_style should always be set by static default"),
+            partial_config.url.as_deref(),
+            partial_config.bucket.as_deref(),
+        )?;
+        let stdin = paths.iter().any(|p| p.as_os_str() == STDIN_PATH);
+        if stdin && paths.len() > 1 {
+            return Err(ConfigError::Parse(
+                "stdin can not be uploaded together with other paths".to_string(),
+            ));
+        }
+        let expires = parse_duration_setting("expires", partial_config.expires.as_deref())?
+            .expect("expires should always be set by static default");
+        let link_expires =
+            parse_duration_setting("linkExpires", partial_config.link_expires.as_deref())?;
+        let link_expires = check_link_expires(expires, link_expires, public)?;
+        Ok(Parsed::Config(Box::new(Config {
+            command,
+            expires,
             bucket: partial_config
                 .bucket
                 .ok_or_else(|| ConfigError::Missing("bucket".to_string()))?,
@@ -204,11 +562,8 @@ impl Config {
                 .url
                 // make into error
                 .ok_or(ConfigError::Missing("url".to_string()))?,
-            path: partial_config
-                .path
-                .ok_or_else(|| ConfigError::Missing("path".to_string()))?
-                .canonicalize()
-                .map_err(|e| ConfigError::Parse(format!("Could not canonicalize path: {}", e)))?,
+            paths,
+            stdin,
             credentials: partial_config
                 .credentials
                 .ok_or_else(|| ConfigError::Missing("credentials".to_string()))?,
@@ -224,7 +579,177 @@ impl Config {
             qr: partial_config
                 .qr
                 .expect("qr should always be set by static default"),
+            retries,
+            resume: partial_config
+                .resume
+                .expect("resume should always be set by static default"),
+            part_size,
+            concurrency,
+            verify: partial_config
+                .verify
+                .expect("verify should always be set by static default"),
+            content_type: partial_config.content_type,
+            name: partial_config.name,
+            inline: partial_config
+                .inline
+                .expect("inline should always be set by static default"),
+            force_upload: partial_config
+                .force_upload
+                .expect("force_upload should always be set by static default"),
+            sse,
+            encrypt,
+            storage_class,
+            meta,
+            default_meta: partial_config
+                .default_meta
+                .expect("default_meta should always be set by static default"),
+            url_style,
+            public,
+            public_base_url,
+            link_expires,
+        })))
+    }
+}
+
+/// Expand glob patterns (for shells that do not) and canonicalize all paths.
+/// `-` for stdin is kept as it is.
+fn expand_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>, ConfigError> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        expanded.extend(expand_path(path)?);
+    }
+    Ok(expanded)
+}
+
+/// Expand `path` if it is a glob pattern, and canonicalize the result.
+/// `-` for stdin is kept as it is.
+pub fn expand_path(path: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    if path.as_os_str() == STDIN_PATH {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let pattern = path.to_string_lossy();
+    let is_pattern = !path.exists() && pattern.contains(['*', '?', '[']);
+    if !is_pattern {
+        let path = path.canonicalize().map_err(|e| {
+            ConfigError::Parse(format!("Path {} does not exist: {}", path.display(), e))
+        })?;
+        return Ok(vec![path]);
+    }
+    let matches = glob::glob(&pattern)
+        .map_err(|e| ConfigError::Parse(format!("Invalid pattern \"{pattern}\": {e}")))?
+        .filter_map(|p| p.ok())
+        .map(|p| p.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    if matches.is_empty() {
+        return Err(ConfigError::Parse(format!(
+            "Pattern \"{pattern}\" does not match any path"
+        )));
+    }
+    Ok(matches)
+}
+
+/// Parse `path`, `virtual` or `auto`, or `on` and `off` as used by the `path`
+/// of `credentials.json`. `auto` picks a style that works for `url` and `bucket`.
+fn parse_url_style(
+    style: &str,
+    url: Option<&str>,
+    bucket: Option<&str>,
+) -> Result<UrlStyle, ConfigError> {
+    match style {
+        "path" | "on" => Ok(UrlStyle::Path),
+        "virtual" | "off" => Ok(UrlStyle::VirtualHost),
+        "auto" => Ok(auto_url_style(
+            url.and_then(|url| url.parse().ok()).as_ref(),
+            bucket.unwrap_or_default(),
+        )),
+        _ => Err(ConfigError::Parse(format!(
+            "Invalid url style \"{style}\", expected path, virtual or auto"
+        ))),
+    }
+}
+
+/// AWS endpoints get virtual-hosted-style URLs, for which the bucket name must be
+/// a valid host name without dots, or TLS certificates do not match.
+/// Everything else, like IPs, localhost and MinIO, gets path-style URLs.
+fn auto_url_style(url: Option<&Url>, bucket: &str) -> UrlStyle {
+    let is_aws = url.and_then(|url| url.domain()).is_some_and(|domain| {
+        domain.ends_with(".amazonaws.com") || domain.ends_with(".amazonaws.com.cn")
+    });
+    let is_host_name = !bucket.is_empty()
+        && bucket
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if is_aws && is_host_name {
+        UrlStyle::VirtualHost
+    } else {
+        UrlStyle::Path
+    }
+}
+
+/// The metadata of both sources, where the entries of `higher` replace those
+/// of `lower` with the same key.
+fn merge_meta(
+    higher: Option<Vec<(String, String)>>,
+    lower: Option<Vec<(String, String)>>,
+) -> Option<Vec<(String, String)>> {
+    let Some(higher) = higher else {
+        return lower;
+    };
+    let mut meta: Vec<_> = lower
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| !higher.iter().any(|(k, _)| k == key))
+        .collect();
+    meta.extend(higher);
+    Some(meta)
+}
+
+/// Parse a duration like `3d` from the setting `name`, if it is set.
+fn parse_duration_setting(name: &str, value: Option<&str>) -> Result<Option<u32>, ConfigError> {
+    value
+        .map(|value| {
+            get_time_from_str(value)
+                .ok_or_else(|| ConfigError::Parse(format!("Could not parse {name}: \"{value}\"")))
         })
+        .transpose()
+}
+
+/// Parse the number in setting `name`, like `retries`.
+fn parse_number_setting<T>(name: &str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| ConfigError::Parse(format!("Could not parse {name} \"{value}\": {e}")))
+}
+
+/// How long links are valid for: `link_expires`, or as long as the upload is
+/// kept but no longer than a presigned link can be valid. Links must neither
+/// outlive the upload nor be longer than S3 accepts. Public links do not
+/// expire, so anything goes for them.
+fn check_link_expires(
+    expires: u32,
+    link_expires: Option<u32>,
+    public: bool,
+) -> Result<u32, ConfigError> {
+    let max = link::MAX_LINK_LIFETIME.as_secs() as u32;
+    let format = |secs: u32| humantime::format_duration(Duration::from_secs(secs.into()));
+    match link_expires {
+        None => Ok(expires.min(max)),
+        Some(link_expires) if public => Ok(link_expires),
+        Some(link_expires) if link_expires > max => Err(ConfigError::Parse(format!(
+            "Links can be valid for at most {}, not {}. Keep the upload longer with --expires and get a new link later with `crab_share relink`",
+            format(max),
+            format(link_expires)
+        ))),
+        Some(link_expires) if link_expires > expires => Err(ConfigError::Parse(format!(
+            "The link would outlive the upload: --link-expires {} is longer than --expires {}",
+            format(link_expires),
+            format(expires)
+        ))),
+        Some(link_expires) => Ok(link_expires),
     }
 }
 
@@ -240,3 +765,140 @@ fn get_time_from_str(input: &str) -> Option<u32> {
         _ => Some(input.parse::<u32>().ok()?),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use reqwest::Url;
+    use rusty_s3::UrlStyle;
+
+    use super::{
+        auto_url_style, check_link_expires, expand_paths, parse_number_setting, parse_url_style,
+        PartialConfig,
+    };
+
+    #[test]
+    fn expands_globs() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let paths = expand_paths(&[dir.join("src/config/*.rs"), dir.join("Cargo.toml")]).unwrap();
+
+        assert!(paths.contains(&dir.join("src/config/mod.rs").canonicalize().unwrap()));
+        assert!(paths.contains(&dir.join("src/config/args.rs").canonicalize().unwrap()));
+        assert_eq!(
+            paths.last().unwrap(),
+            &dir.join("Cargo.toml").canonicalize().unwrap()
+        );
+        assert!(!paths.iter().any(|p| p.ends_with("src/main.rs")));
+    }
+
+    #[test]
+    fn merges_metadata_of_all_sources() {
+        let meta = |entries: &[(&str, &str)]| PartialConfig {
+            meta: Some(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let args = meta(&[("project", "crab"), ("ticket", "42")]);
+        let json = meta(&[("team", "infra"), ("project", "old")]);
+
+        assert_eq!(
+            args.merge(json).meta.unwrap(),
+            vec![
+                ("team".to_string(), "infra".to_string()),
+                ("project".to_string(), "crab".to_string()),
+                ("ticket".to_string(), "42".to_string()),
+            ]
+        );
+        let merged = PartialConfig::default().merge(meta(&[("team", "infra")]));
+        assert_eq!(merged.meta.unwrap().len(), 1);
+        assert!(PartialConfig::default()
+            .merge(PartialConfig::default())
+            .meta
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        assert_eq!(parse_number_setting::<u32>("retries", "3").unwrap(), 3);
+        let e = parse_number_setting::<u32>("retries", "five").unwrap_err();
+        assert!(e.to_string().contains("Could not parse retries \"five\""));
+        assert!(parse_number_setting::<usize>("concurrency", "-1").is_err());
+    }
+
+    #[test]
+    fn keeps_stdin_and_rejects_missing_paths() {
+        assert_eq!(
+            expand_paths(&[PathBuf::from("-")]).unwrap(),
+            vec![PathBuf::from("-")]
+        );
+        assert!(expand_paths(&[PathBuf::from("/does/not/exist")]).is_err());
+        assert!(expand_paths(&[PathBuf::from("/does/not/*.exist")]).is_err());
+    }
+
+    #[test]
+    fn picks_url_style_for_endpoint() {
+        let style =
+            |url: &str, bucket: &str| auto_url_style(Some(&Url::parse(url).unwrap()), bucket);
+
+        assert!(matches!(
+            style("https://s3.eu-central-1.amazonaws.com", "shares"),
+            UrlStyle::VirtualHost
+        ));
+        assert!(matches!(
+            style("https://s3.amazonaws.com", "my.shares"),
+            UrlStyle::Path
+        ));
+        assert!(matches!(
+            style("http://127.0.0.1:9000", "shares"),
+            UrlStyle::Path
+        ));
+        assert!(matches!(
+            style("http://[::1]:9000", "shares"),
+            UrlStyle::Path
+        ));
+        assert!(matches!(
+            style("http://localhost:9000", "shares"),
+            UrlStyle::Path
+        ));
+        assert!(matches!(
+            style("https://minio.example.com", "shares"),
+            UrlStyle::Path
+        ));
+        assert!(matches!(auto_url_style(None, "shares"), UrlStyle::Path));
+    }
+
+    #[test]
+    fn explicit_url_style_wins() {
+        let url = Some("http://127.0.0.1:9000");
+        assert!(matches!(
+            parse_url_style("virtual", url, Some("b")).unwrap(),
+            UrlStyle::VirtualHost
+        ));
+        assert!(matches!(
+            parse_url_style("on", url, Some("b")).unwrap(),
+            UrlStyle::Path
+        ));
+        assert!(parse_url_style("vhost", url, Some("b")).is_err());
+    }
+
+    #[test]
+    fn links_are_valid_for_at_most_seven_days() {
+        const DAY: u32 = 24 * 60 * 60;
+
+        assert_eq!(check_link_expires(3 * DAY, None, false).unwrap(), 3 * DAY);
+        assert_eq!(check_link_expires(30 * DAY, None, false).unwrap(), 7 * DAY);
+        assert_eq!(check_link_expires(30 * DAY, Some(DAY), false).unwrap(), DAY);
+        assert!(check_link_expires(30 * DAY, Some(8 * DAY), false).is_err());
+        assert!(check_link_expires(DAY, Some(2 * DAY), false).is_err());
+        // public links do not expire
+        assert_eq!(
+            check_link_expires(30 * DAY, Some(30 * DAY), true).unwrap(),
+            30 * DAY
+        );
+    }
+}
@@ -0,0 +1,205 @@
+// guess the content type of an upload, so browsers can show it instead of downloading it
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Used when nothing else matches.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// How many bytes are read from the start of a file to sniff its type.
+const SNIFF_LEN: usize = 512;
+
+/// Guess the content type of the file at `path` that is uploaded as `name`.
+///
+/// The extension of `name` wins, otherwise the first bytes of the file are
+/// checked for well-known signatures.
+pub fn detect(name: &str, path: &Path) -> &'static str {
+    if let Some(content_type) = from_extension(name) {
+        return content_type;
+    }
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    match File::open(path).map(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut head)) {
+        Ok(Ok(_)) => from_magic(&head),
+        _ => DEFAULT_CONTENT_TYPE,
+    }
+}
+
+/// Guess the content type of a stream that is uploaded as `name` and starts with `head`.
+pub fn detect_head(name: &str, head: &[u8]) -> &'static str {
+    from_extension(name).unwrap_or_else(|| from_magic(&head[..head.len().min(SNIFF_LEN)]))
+}
+
+fn from_extension(name: &str) -> Option<&'static str> {
+    let (_, extension) = name.rsplit_once('.')?;
+    let content_type = match extension.to_ascii_lowercase().as_str() {
+        // text
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "yaml" | "yml" => "application/yaml",
+        "toml" => "application/toml",
+        // documents
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        // images
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "heic" => "image/heic",
+        "svg" => "image/svg+xml",
+        "ico" => "image/vnd.microsoft.icon",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        // audio and video
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "ogv" => "video/ogg",
+        // archives
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "bz2" => "application/x-bzip2",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        // fonts and everything else
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+fn from_magic(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"\x00asm", "application/wasm"),
+        (b"<?xml", "application/xml"),
+    ];
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(sig, _)| head.starts_with(sig)) {
+        return content_type;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+    // ISO base media files: mp4, mov, heic, avif, ...
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return match &head[8..12] {
+            b"qt  " => "video/quicktime",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"avif" => "image/avif",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        };
+    }
+    if head.len() > 262 && &head[257..262] == b"ustar" {
+        return "application/x-tar";
+    }
+    let start = String::from_utf8_lossy(&head[..head.len().min(64)]).to_ascii_lowercase();
+    let start = start.trim_start();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return "text/html; charset=utf-8";
+    }
+    if is_text(head) {
+        return "text/plain; charset=utf-8";
+    }
+    DEFAULT_CONTENT_TYPE
+}
+
+/// Whether `head` looks like UTF-8 text. The last character may be cut off.
+fn is_text(head: &[u8]) -> bool {
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_extension, from_magic, DEFAULT_CONTENT_TYPE};
+
+    #[test]
+    fn detects_by_extension() {
+        assert_eq!(from_extension("report.PDF"), Some("application/pdf"));
+        assert_eq!(from_extension("photos.zip"), Some("application/zip"));
+        assert_eq!(from_extension("archive.tar.gz"), Some("application/gzip"));
+        assert_eq!(from_extension("Makefile"), None);
+        assert_eq!(from_extension("file.unknown"), None);
+    }
+
+    #[test]
+    fn detects_by_magic_bytes() {
+        assert_eq!(from_magic(b"%PDF-1.7\n..."), "application/pdf");
+        assert_eq!(from_magic(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(from_magic(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(from_magic(b"\0\0\0\x20ftypisom\0\0\x02\0"), "video/mp4");
+        assert_eq!(
+            from_magic(b"  <!DOCTYPE html><html>"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            from_magic("grüße\n".as_bytes()),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_octet_stream() {
+        assert_eq!(from_magic(b""), DEFAULT_CONTENT_TYPE);
+        assert_eq!(from_magic(b"\x00\x01\x02\x03"), DEFAULT_CONTENT_TYPE);
+        assert_eq!(from_magic(b"\xff\xfe\xfd"), DEFAULT_CONTENT_TYPE);
+    }
+}
@@ -0,0 +1,168 @@
+// download and decrypt a share that was uploaded with --encrypt
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use bytesize::ByteSize;
+use reqwest::Client;
+
+use crate::config::DecryptOptions;
+use crate::encrypt::{Decryptor, Key};
+use crate::progress::download_progress_bar;
+
+/// Name of the decrypted file if the share has none that can be used safely.
+const FALLBACK_NAME: &str = "decrypted";
+
+/// Download the share at `options.url` and decrypt it with the key in the
+/// fragment of the link.
+///
+/// The plaintext is written next to its destination first and only gets its
+/// name once the whole download is authenticated.
+pub async fn decrypt(options: &DecryptOptions, client: &Client) {
+    let (url, key) = match split_link(&options.url) {
+        Ok(link) => link,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+    let mut partial = None;
+    match download(client, url, key, options.output.as_deref(), &mut partial).await {
+        Ok((path, size)) => println!("decrypted {} to {}", ByteSize(size), path.display()),
+        Err(e) => {
+            if let Some(partial) = partial {
+                let _ = fs::remove_file(partial);
+            }
+            eprintln!("error decrypting share: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Split a link into the URL to download and the key after its `#`.
+fn split_link(link: &str) -> Result<(&str, Key), String> {
+    let (url, key) = link
+        .split_once('#')
+        .ok_or("the link has no key, it must end with #<key>")?;
+    Ok((url, Key::decode(key)?))
+}
+
+/// Download and decrypt `url`, keeping the path of the unfinished file in
+/// `partial`. Returns where the file was written and its size.
+async fn download(
+    client: &Client,
+    url: &str,
+    key: Key,
+    output: Option<&Path>,
+    partial: &mut Option<PathBuf>,
+) -> Result<(PathBuf, u64), String> {
+    let mut resp = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let progress_bar = download_progress_bar(resp.content_length());
+
+    let mut decryptor = Decryptor::new(key);
+    let mut file = None;
+    let mut size = 0;
+    while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+        progress_bar.inc(chunk.len() as u64);
+        let plaintext = decryptor.update(&chunk).map_err(|e| e.to_string())?;
+        // the name comes first, before any content
+        if file.is_none() {
+            if let Some(name) = decryptor.name() {
+                let path = destination(output, name);
+                file = Some((create_partial(&path, partial)?, path));
+            }
+        }
+        if let Some((file, _)) = &mut file {
+            file.write_all(&plaintext).map_err(|e| e.to_string())?;
+            size += plaintext.len() as u64;
+        }
+    }
+    progress_bar.finish();
+
+    let plaintext = decryptor.finish().map_err(|e| e.to_string())?;
+    let (mut file, path) = file.expect("the name is decrypted before the last chunk");
+    file.write_all(&plaintext)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| e.to_string())?;
+    size += plaintext.len() as u64;
+    let partial = partial.take().expect("the file was created");
+    fs::rename(&partial, &path).map_err(|e| e.to_string())?;
+    Ok((path, size))
+}
+
+/// Where to write the file called `name`: `output`, inside `output` if it is
+/// a directory, or the current directory. Only the last component of `name`
+/// is used, so a share can not write anywhere else.
+fn destination(output: Option<&Path>, name: &str) -> PathBuf {
+    let name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| FALLBACK_NAME.into());
+    match output {
+        Some(output) if output.is_dir() => output.join(name),
+        Some(output) => output.to_path_buf(),
+        None => PathBuf::from(name),
+    }
+}
+
+/// Create the unfinished file for `path`, without overwriting anything.
+fn create_partial(path: &Path, partial: &mut Option<PathBuf>) -> Result<File, String> {
+    if path.exists() {
+        return Err(format!(
+            "{} already exists, choose another path with --output",
+            path.display()
+        ));
+    }
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    let partial_path = path.with_file_name(name);
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&partial_path)
+        .map_err(|e| format!("could not create {}: {}", partial_path.display(), e))?;
+    *partial = Some(partial_path);
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{destination, split_link};
+
+    #[test]
+    fn key_is_taken_from_the_fragment() {
+        let link = format!(
+            "https://s3.example.com/b/k?X-Amz-Signature=1#{}",
+            "A".repeat(43)
+        );
+        let (url, _) = split_link(&link).unwrap();
+        assert_eq!(url, "https://s3.example.com/b/k?X-Amz-Signature=1");
+        assert!(split_link("https://s3.example.com/b/k").is_err());
+        assert!(split_link("https://s3.example.com/b/k#short").is_err());
+    }
+
+    #[test]
+    fn names_can_not_escape_the_destination() {
+        assert_eq!(destination(None, "notes.txt"), PathBuf::from("notes.txt"));
+        assert_eq!(destination(None, "../../.bashrc"), PathBuf::from(".bashrc"));
+        assert_eq!(destination(None, "/etc/passwd"), PathBuf::from("passwd"));
+        assert_eq!(destination(None, ".."), PathBuf::from("decrypted"));
+        assert_eq!(
+            destination(Some(Path::new("/tmp")), "notes.txt"),
+            PathBuf::from("/tmp/notes.txt")
+        );
+        assert_eq!(
+            destination(Some(Path::new("out.bin")), "notes.txt"),
+            PathBuf::from("out.bin")
+        );
+    }
+}
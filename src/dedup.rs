@@ -0,0 +1,223 @@
+// find an earlier upload of the same content, to link it again instead of uploading a copy
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::SystemTime;
+
+use reqwest::Client;
+use rusty_s3::actions::{HeadObject, S3Action};
+use rusty_s3::Bucket;
+use sha2::{Digest, Sha256};
+
+use crate::checksum::hex_digest;
+use crate::config::Config;
+use crate::purge::list_objects;
+use crate::sse;
+use crate::upload::{send, sign_headers, with_headers, UploadError, MAX_COPY_OBJECT_SIZE};
+use crate::{link, ONE_HOUR};
+
+/// Object metadata with the hex encoded SHA-256 of the object.
+pub const SHA256_METADATA: &str = "x-amz-meta-sha256";
+
+/// How an earlier upload of the same content is used instead of uploading it again.
+#[derive(Debug, PartialEq)]
+pub enum Reuse {
+    /// Link the object with this key, it lives as long as a new upload would
+    Link(String),
+    /// Copy the object with this key on the server, it expires too soon to be linked
+    Copy(String),
+}
+
+/// Objects that could be a copy of a new upload, with their key, size and
+/// expiry, the longest living first. What they contain is only checked by
+/// [`Candidates::find`].
+pub struct Candidates(Vec<(String, u64, SystemTime)>);
+
+impl Candidates {
+    /// List the objects in the bucket that were uploaded by crab_share. Public
+    /// links must not point to guessable keys, so those are left out with `--public`.
+    pub async fn list(
+        config: &Config,
+        bucket: &Bucket,
+        client: &Client,
+    ) -> Result<Self, UploadError> {
+        let objects = list_objects(config, bucket, client).await?;
+        let mut candidates: Vec<_> = objects
+            .into_iter()
+            .filter(|o| !config.public || link::is_unguessable(&o.key))
+            .filter_map(|o| Some((o.size, link::key_expiry(&o.key)?, o.key)))
+            .map(|(size, expiry, key)| (key, size, expiry))
+            .collect();
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.2));
+        Ok(Self(candidates))
+    }
+
+    /// Find an unexpired object of `size` bytes whose SHA-256 metadata is
+    /// `sha256` and that is encrypted like `--sse` asks for, preferring the one
+    /// that lives longest. Returns its key and expiry. Only objects of the same
+    /// size are fetched with `HeadObject`, those that can not be are skipped.
+    pub async fn find(
+        &self,
+        config: &Config,
+        bucket: &Bucket,
+        client: &Client,
+        size: u64,
+        sha256: &str,
+    ) -> Result<Option<(String, SystemTime)>, UploadError> {
+        let headers = sse::customer_headers(&config.sse);
+        for (key, _, expiry) in self.of_size(size) {
+            let mut action = HeadObject::new(bucket, Some(&config.credentials), key);
+            sign_headers(action.headers_mut(), &headers);
+            let url = action.sign(ONE_HOUR);
+            let resp = match send(
+                with_headers(client.head(url), &headers),
+                "looking for a copy",
+            )
+            .await
+            {
+                Ok(resp) => resp,
+                // objects encrypted with an SSE-C key, or another one, can not be read
+                Err(_) => continue,
+            };
+            if !sse::matches(&config.sse, resp.headers()) {
+                continue;
+            }
+            let stored = resp
+                .headers()
+                .get(SHA256_METADATA)
+                .and_then(|v| v.to_str().ok());
+            if stored.is_some_and(|stored| stored.eq_ignore_ascii_case(sha256)) {
+                return Ok(Some((key.clone(), *expiry)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The unexpired objects of `size` bytes, the longest living first.
+    fn of_size(&self, size: u64) -> impl Iterator<Item = &(String, u64, SystemTime)> {
+        let now = SystemTime::now();
+        self.0
+            .iter()
+            .filter(move |(_, s, e)| *s == size && *e > now)
+    }
+
+    /// Whether an unexpired object has `size` bytes. Only then is a file of
+    /// that size worth hashing before it is uploaded.
+    pub fn has_size(&self, size: u64) -> bool {
+        self.of_size(size).next().is_some()
+    }
+}
+
+/// The object header that stores `sha256` with an upload.
+pub fn metadata(sha256: &str) -> (String, String) {
+    (SHA256_METADATA.to_string(), sha256.to_string())
+}
+
+/// How `found`, an earlier upload of `size` bytes with its expiry, is used
+/// for an upload that is kept until `expiry`. Links must not outlive what
+/// they point to, so an earlier upload that expires too soon is copied to a
+/// new key. Objects too large for a single `CopyObject` are uploaded again.
+pub fn reuse(found: Option<(String, SystemTime)>, size: u64, expiry: SystemTime) -> Option<Reuse> {
+    match found? {
+        (key, found_expiry) if found_expiry >= expiry => Some(Reuse::Link(key)),
+        (key, _) if size <= MAX_COPY_OBJECT_SIZE => Some(Reuse::Copy(key)),
+        _ => None,
+    }
+}
+
+/// The candidates for a file of `size` bytes, `None` if no unexpired object
+/// has that size and the file does not need to be hashed up front.
+/// A failed listing is only a warning, the file is uploaded again then.
+pub async fn candidates_for(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    size: u64,
+) -> Option<Candidates> {
+    match Candidates::list(config, bucket, client).await {
+        Ok(candidates) => Some(candidates).filter(|c| c.has_size(size)),
+        Err(e) => {
+            eprintln!("warn: could not look for an earlier upload: {}", e);
+            None
+        }
+    }
+}
+
+/// Look among `candidates` for an unexpired object of `size` bytes with
+/// `sha256`, for an upload that is kept until `expiry`.
+/// A failed lookup is only a warning, the file is uploaded again then.
+pub async fn find_copy(
+    candidates: &Candidates,
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    size: u64,
+    sha256: &str,
+    expiry: SystemTime,
+) -> Option<Reuse> {
+    match candidates.find(config, bucket, client, size, sha256).await {
+        Ok(found) => reuse(found, size, expiry),
+        Err(e) => {
+            eprintln!("warn: could not look for an earlier upload: {}", e);
+            None
+        }
+    }
+}
+
+/// The hex encoded SHA-256 of everything `reader` has left.
+pub fn sha256_of(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex_digest(hasher))
+}
+
+/// The hex encoded SHA-256 of `file`, read on a blocking thread. Afterwards
+/// the file is back at its start.
+pub async fn file_sha256(file: &File) -> io::Result<String> {
+    let mut file = file.try_clone()?;
+    tokio::task::spawn_blocking(move || {
+        file.seek(SeekFrom::Start(0))?;
+        let sha256 = sha256_of(&mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(sha256)
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{reuse, sha256_of, Reuse};
+    use crate::upload::MAX_COPY_OBJECT_SIZE;
+
+    #[test]
+    fn hashes_whole_input() {
+        assert_eq!(
+            sha256_of(&b"0123456789"[..]).unwrap(),
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+    }
+
+    #[test]
+    fn copies_uploads_that_expire_too_soon() {
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let hour = Duration::from_secs(3600);
+        let found = |expiry| Some(("01KK3RNR08TGMCJ9E1W0949R1Y/a.txt".to_string(), expiry));
+
+        assert_eq!(
+            reuse(found(expiry + hour), 10, expiry),
+            Some(Reuse::Link("01KK3RNR08TGMCJ9E1W0949R1Y/a.txt".to_string()))
+        );
+        assert_eq!(
+            reuse(found(expiry - hour), 10, expiry),
+            Some(Reuse::Copy("01KK3RNR08TGMCJ9E1W0949R1Y/a.txt".to_string()))
+        );
+        assert_eq!(
+            reuse(found(expiry - hour), MAX_COPY_OBJECT_SIZE + 1, expiry),
+            None
+        );
+        assert_eq!(reuse(None, 10, expiry), None);
+    }
+}
@@ -0,0 +1,142 @@
+// delete shares, which is the only way to revoke their links
+
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+
+use bytesize::ByteSize;
+use reqwest::Client;
+use rusty_s3::Bucket;
+
+use crate::config::{Config, DeleteOptions};
+use crate::link;
+use crate::purge::{delete_object, list_objects_below};
+
+/// How a share is named on the command line.
+#[derive(Debug, PartialEq)]
+enum Share {
+    /// All objects below the ULID, which is all of a share without knowing its name
+    Ulid(String),
+    /// The key of the object
+    Key(String),
+}
+
+impl Share {
+    fn parse(config: &Config, bucket: &Bucket, share: &str) -> Result<Self, String> {
+        if let Ok(ulid) = share.parse::<ulid::Ulid>() {
+            return Ok(Share::Ulid(ulid.to_string()));
+        }
+        link::link_key(bucket, config.public_base_url.as_deref(), share).map(Share::Key)
+    }
+
+    /// The prefix to list the share with.
+    fn prefix(&self) -> String {
+        match self {
+            Share::Ulid(ulid) => format!("{ulid}/"),
+            Share::Key(key) => key.clone(),
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Share::Ulid(_) => true,
+            Share::Key(k) => k == key,
+        }
+    }
+}
+
+/// Delete the shares in `options`, after asking unless `--yes` is given.
+/// Nothing is deleted if any of them can not be found.
+pub async fn delete(config: &Config, options: &DeleteOptions, bucket: &Bucket, client: &Client) {
+    let mut objects: Vec<(String, u64)> = Vec::new();
+    for arg in &options.shares {
+        let share = match Share::parse(config, bucket, arg) {
+            Ok(share) => share,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                exit(1);
+            }
+        };
+        let listed = match list_objects_below(config, bucket, client, &share.prefix()).await {
+            Ok(listed) => listed,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        };
+        let found: Vec<_> = listed
+            .into_iter()
+            .filter(|object| share.matches(&object.key))
+            .map(|object| (object.key, object.size))
+            .collect();
+        if found.is_empty() {
+            eprintln!(
+                "error: there is no share {} in bucket {}",
+                arg,
+                bucket.name()
+            );
+            exit(1);
+        }
+        for object in found {
+            if !objects.contains(&object) {
+                objects.push(object);
+            }
+        }
+    }
+
+    for (key, size) in &objects {
+        eprintln!("{}  {}", key, ByteSize(*size));
+    }
+    if !options.yes && !confirm(&format!("Delete {} share(s)?", objects.len())) {
+        eprintln!("Nothing was deleted");
+        exit(1);
+    }
+    for (key, _) in &objects {
+        if let Err(e) = delete_object(config, bucket, client, key).await {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        println!("deleted {}", key);
+    }
+}
+
+/// Ask `question` on the terminal. Only `y` or `yes` is a yes, so nothing
+/// happens if stdin is closed or not a terminal.
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    is_yes(&answer)
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_yes, Share};
+
+    #[test]
+    fn ulids_match_the_whole_share() {
+        let share = Share::Ulid("01KK3RNR08TGMCJ9E1W0949R1Y".to_string());
+        assert_eq!(share.prefix(), "01KK3RNR08TGMCJ9E1W0949R1Y/");
+        assert!(share.matches("01KK3RNR08TGMCJ9E1W0949R1Y/report.pdf"));
+
+        let share = Share::Key("01KK3RNR08TGMCJ9E1W0949R1Y/report.pdf".to_string());
+        assert_eq!(share.prefix(), "01KK3RNR08TGMCJ9E1W0949R1Y/report.pdf");
+        assert!(share.matches("01KK3RNR08TGMCJ9E1W0949R1Y/report.pdf"));
+        assert!(!share.matches("01KK3RNR08TGMCJ9E1W0949R1Y/report.pdf.bak"));
+    }
+
+    #[test]
+    fn only_yes_confirms() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" YES\r\n"));
+        assert!(!is_yes(""));
+        assert!(!is_yes("n\n"));
+        assert!(!is_yes("yess\n"));
+    }
+}
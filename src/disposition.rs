@@ -0,0 +1,63 @@
+// the Content-Disposition of shared links, so downloads keep their name
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything but the `attr-char`s of RFC 5987 is percent-encoded.
+pub const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// The `Content-Disposition` for downloading (or, if `inline`, showing) a file
+/// called `name`.
+///
+/// Browsers use the RFC 5987 `filename*` with the full UTF-8 name, older
+/// clients fall back to `filename`, where everything that is not printable
+/// ASCII is replaced.
+pub fn content_disposition(name: &str, inline: bool) -> String {
+    let disposition = if inline { "inline" } else { "attachment" };
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        utf8_percent_encode(name, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::content_disposition;
+
+    #[test]
+    fn encodes_ascii_names() {
+        assert_eq!(
+            content_disposition("report 2024.pdf", false),
+            "attachment; filename=\"report 2024.pdf\"; filename*=UTF-8''report%202024.pdf"
+        );
+    }
+
+    #[test]
+    fn encodes_utf8_names() {
+        assert_eq!(
+            content_disposition("Grüße \"ä\".txt", true),
+            "inline; filename=\"Gr__e ___.txt\"; filename*=UTF-8''Gr%C3%BC%C3%9Fe%20%22%C3%A4%22.txt"
+        );
+    }
+}
@@ -0,0 +1,340 @@
+// end-to-end encryption of uploads, with the key only in the link
+//
+// An encrypted upload starts with a header:
+//
+//   magic (7 bytes) | version (1 byte) | nonce prefix (19 bytes) | name length (u16, big endian)
+//
+// followed by the STREAM construction of XChaCha20-Poly1305: the file name, then
+// the content in chunks of `CHUNK_SIZE` bytes, each with a 16 byte tag. The last
+// chunk is marked, so a truncated download does not decrypt. Every chunk
+// authenticates the header as associated data.
+
+use std::io;
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+
+const MAGIC: &[u8; 7] = b"crabenc";
+const VERSION: u8 = 1;
+/// XChaCha20 nonces are 24 bytes, STREAM uses 5 of them for its counter.
+const NONCE_PREFIX_LEN: usize = 19;
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_PREFIX_LEN + 2;
+const TAG_LEN: usize = 16;
+/// Size of the plaintext chunks that are encrypted one by one.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Object name of encrypted uploads, the real one is encrypted with the content.
+pub const ENCRYPTED_NAME: &str = "encrypted";
+pub const ENCRYPTED_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// A random 256 bit key, that only ever leaves the machine in the link.
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn generate() -> Self {
+        Key(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    /// The key as it is appended to the link, after a `#`.
+    pub fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(self.0)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, String> {
+        let key = BASE64_URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|e| format!("the key in the link is not valid: {e}"))?;
+        let key = key
+            .try_into()
+            .map_err(|_| "the key in the link has the wrong length".to_string())?;
+        Ok(Key(key))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+/// Never print the key.
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+fn crypto_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "decryption failed, the key is wrong or the data was modified",
+    )
+}
+
+/// Encrypts a stream of unknown length chunk by chunk.
+pub struct Encryptor {
+    /// `None` once the last chunk is encrypted
+    stream: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    header: [u8; HEADER_LEN],
+    chunk: Vec<u8>,
+    /// Ciphertext that was not taken yet
+    out: Vec<u8>,
+}
+
+impl Encryptor {
+    /// Start encrypting a file called `name` with `key`. The header and the
+    /// encrypted name are the first output.
+    pub fn new(key: &Key, name: &str) -> io::Result<Self> {
+        let name = name.as_bytes();
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| io::Error::other("the file name is too long to be encrypted"))?;
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        let mut header = [0; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[MAGIC.len() + 1..HEADER_LEN - 2].copy_from_slice(&nonce_prefix);
+        header[HEADER_LEN - 2..].copy_from_slice(&name_len.to_be_bytes());
+
+        let mut stream = EncryptorBE32::from_aead(key.cipher(), nonce_prefix.as_ref().into());
+        let mut out = header.to_vec();
+        out.extend(
+            stream
+                .encrypt_next(Payload {
+                    msg: name,
+                    aad: &header,
+                })
+                .map_err(|_| io::Error::other("could not encrypt the file name"))?,
+        );
+        Ok(Self {
+            stream: Some(stream),
+            header,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            out,
+        })
+    }
+
+    /// Encrypt `data`. A full chunk is only encrypted once more data follows,
+    /// because the last chunk is encrypted differently.
+    pub fn update(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            if self.chunk.len() == CHUNK_SIZE {
+                let stream = self.stream.as_mut().expect("encryptor is finished");
+                let chunk = stream
+                    .encrypt_next(Payload {
+                        msg: &self.chunk,
+                        aad: &self.header,
+                    })
+                    .map_err(|_| io::Error::other("input is too large to be encrypted"))?;
+                self.out.extend(chunk);
+                self.chunk.clear();
+            }
+            let len = data.len().min(CHUNK_SIZE - self.chunk.len());
+            self.chunk.extend_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// The ciphertext produced so far.
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Encrypt the last chunk. Returns the rest of the ciphertext.
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        let stream = self.stream.take().expect("encryptor is finished");
+        let chunk = stream
+            .encrypt_last(Payload {
+                msg: &self.chunk,
+                aad: &self.header,
+            })
+            .map_err(|_| io::Error::other("could not encrypt the last chunk"))?;
+        self.out.extend(chunk);
+        Ok(self.out)
+    }
+}
+
+/// Decrypts what an [`Encryptor`] produced, as it is downloaded.
+pub struct Decryptor {
+    key: Key,
+    stream: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    header: [u8; HEADER_LEN],
+    name: Option<String>,
+    /// Ciphertext that does not make up a whole chunk yet
+    buffer: Vec<u8>,
+}
+
+impl Decryptor {
+    pub fn new(key: Key) -> Self {
+        Self {
+            key,
+            stream: None,
+            header: [0; HEADER_LEN],
+            name: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// The name of the encrypted file, once it is decrypted.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Decrypt `data`. Returns the plaintext of all chunks that are complete,
+    /// except the last one, which is only decrypted by [`Decryptor::finish`].
+    pub fn update(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut plaintext = Vec::new();
+        if self.stream.is_none() {
+            if self.buffer.len() < HEADER_LEN {
+                return Ok(plaintext);
+            }
+            self.read_header()?;
+        }
+        if self.name.is_none() {
+            let name_len =
+                u16::from_be_bytes([self.header[HEADER_LEN - 2], self.header[HEADER_LEN - 1]])
+                    as usize
+                    + TAG_LEN;
+            if self.buffer.len() < name_len {
+                return Ok(plaintext);
+            }
+            let name = self.decrypt_next(name_len)?;
+            self.name = Some(String::from_utf8_lossy(&name).to_string());
+        }
+        // a whole chunk at the end may be the last one
+        while self.buffer.len() > CHUNK_SIZE + TAG_LEN {
+            plaintext.extend(self.decrypt_next(CHUNK_SIZE + TAG_LEN)?);
+        }
+        Ok(plaintext)
+    }
+
+    /// Decrypt the last chunk. Fails if the download was cut short.
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        let truncated =
+            || io::Error::new(io::ErrorKind::UnexpectedEof, "the download is incomplete");
+        if self.name.is_none() || self.buffer.len() > CHUNK_SIZE + TAG_LEN {
+            return Err(truncated());
+        }
+        let stream = self.stream.take().ok_or_else(truncated)?;
+        stream
+            .decrypt_last(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })
+            .map_err(crypto_error)
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let header = &self.buffer[..HEADER_LEN];
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the download is not an encrypted share",
+            ));
+        }
+        if header[MAGIC.len()] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the share is encrypted with the unknown version {}",
+                    header[MAGIC.len()]
+                ),
+            ));
+        }
+        self.header.copy_from_slice(header);
+        let nonce_prefix = &self.header[MAGIC.len() + 1..HEADER_LEN - 2];
+        self.stream = Some(DecryptorBE32::from_aead(
+            self.key.cipher(),
+            nonce_prefix.into(),
+        ));
+        self.buffer.drain(..HEADER_LEN);
+        Ok(())
+    }
+
+    fn decrypt_next(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let stream = self.stream.as_mut().expect("header is read");
+        let plaintext = stream
+            .decrypt_next(Payload {
+                msg: &self.buffer[..len],
+                aad: &self.header,
+            })
+            .map_err(crypto_error)?;
+        self.buffer.drain(..len);
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decryptor, Encryptor, Key, CHUNK_SIZE};
+
+    fn encrypt(key: &Key, name: &str, content: &[u8]) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(key, name).unwrap();
+        let mut ciphertext = Vec::new();
+        for piece in content.chunks(1000) {
+            encryptor.update(piece).unwrap();
+            ciphertext.extend(encryptor.take());
+        }
+        ciphertext.extend(encryptor.finish().unwrap());
+        ciphertext
+    }
+
+    fn decrypt(key: &Key, ciphertext: &[u8]) -> std::io::Result<(String, Vec<u8>)> {
+        let mut decryptor = Decryptor::new(key.clone());
+        let mut plaintext = Vec::new();
+        for piece in ciphertext.chunks(777) {
+            plaintext.extend(decryptor.update(piece)?);
+        }
+        let name = decryptor.name().unwrap_or_default().to_string();
+        plaintext.extend(decryptor.finish()?);
+        Ok((name, plaintext))
+    }
+
+    #[test]
+    fn round_trips_any_length() {
+        let key = Key::generate();
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            let content: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let ciphertext = encrypt(&key, "Grüße.txt", &content);
+            let (name, plaintext) = decrypt(&key, &ciphertext).unwrap();
+            assert_eq!(name, "Grüße.txt");
+            assert_eq!(plaintext, content, "length {len}");
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_key_modified_and_truncated_data() {
+        let key = Key::generate();
+        let ciphertext = encrypt(&key, "a.bin", &vec![7; 2 * CHUNK_SIZE + 10]);
+
+        assert!(decrypt(&Key::generate(), &ciphertext).is_err());
+        let mut modified = ciphertext.clone();
+        modified[100] ^= 1;
+        assert!(decrypt(&key, &modified).is_err());
+        // cut at the end of a whole chunk
+        assert!(decrypt(&key, &ciphertext[..ciphertext.len() - 26]).is_err());
+        assert!(decrypt(&key, &ciphertext[..10]).is_err());
+        assert!(decrypt(&key, b"not encrypted at all, but long enough").is_err());
+    }
+
+    #[test]
+    fn key_survives_the_link() {
+        let key = Key::generate();
+        let encoded = key.encode();
+        assert_eq!(encoded.len(), 43);
+        assert_eq!(Key::decode(&encoded).unwrap().0, key.0);
+        assert!(Key::decode("AAAA").is_err());
+        assert!(Key::decode("not base64!").is_err());
+    }
+}
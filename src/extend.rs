@@ -0,0 +1,209 @@
+// give a share a new expiry by moving it to a key with a new ULID
+
+use std::process::exit;
+use std::time::SystemTime;
+
+use bytesize::ByteSize;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use rusty_s3::Bucket;
+
+use crate::config::{Config, ExtendOptions};
+use crate::metadata::METADATA_PREFIX;
+use crate::parts::{part_size_for, MAX_PARTS};
+use crate::progress::upload_progress_bar;
+use crate::purge::delete_object;
+use crate::upload::{
+    copy_object, head_object, MultipartUpload, ObjectHeaders, MAX_COPY_OBJECT_SIZE,
+};
+use crate::{exit_on_error, link, render_qr_ascii};
+
+/// Parts of larger copies are at least this large. They are copied on the
+/// server, so fewer and larger parts are faster.
+const COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+/// Headers of the object that the copy keeps, besides the metadata.
+const KEPT_HEADERS: [&str; 5] = [
+    "content-type",
+    "content-disposition",
+    "content-encoding",
+    "cache-control",
+    "x-amz-storage-class",
+];
+/// Encryption headers of the object that the copy keeps, unless `--sse` is given.
+const KEPT_SSE_HEADERS: [&str; 2] = [
+    "x-amz-server-side-encryption",
+    "x-amz-server-side-encryption-aws-kms-key-id",
+];
+
+/// Copy the share in `options` to a key that expires after `--expires` from
+/// now, delete the old key and print a link to the new one.
+///
+/// The ULID at the start of a key is its expiry, so a share can not be kept
+/// longer without moving it.
+pub async fn extend(config: &Config, options: &ExtendOptions, bucket: &Bucket, client: &Client) {
+    let key = match link::link_key(bucket, config.public_base_url.as_deref(), &options.share) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+    let Some((_, name)) = key
+        .split_once('/')
+        .filter(|_| link::key_expiry(&key).is_some())
+    else {
+        eprintln!(
+            "error: {} was not uploaded by crab_share, it has no expiry to change",
+            key
+        );
+        exit(1);
+    };
+    let expiry = link::expiry(config);
+    let new_key = format!("{}/{}", ulid::Ulid::from_datetime(expiry), name);
+
+    let head = exit_on_error(head_object(config, bucket, client, &key).await);
+    let size = content_length(&head).unwrap_or_default();
+    let headers = copied_headers(&head, expiry, config.sse.is_none());
+
+    if size <= MAX_COPY_OBJECT_SIZE {
+        println!("copying {} to {}", ByteSize(size), new_key);
+        exit_on_error(copy_object(config, bucket, client, &key, &new_key, &headers).await);
+    } else {
+        let requested = config
+            .part_size
+            .unwrap_or(COPY_PART_SIZE.max(size.div_ceil(MAX_PARTS)));
+        let part_size = match part_size_for(size, Some(requested)) {
+            Ok(part_size) => part_size as u64,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                exit(1);
+            }
+        };
+        println!(
+            "copying {} to {} in parts of {}",
+            ByteSize(size),
+            new_key,
+            ByteSize(part_size)
+        );
+        let upload = exit_on_error(
+            MultipartUpload::create(config, bucket, client, &new_key, &headers).await,
+        );
+        let progress_bar = upload_progress_bar(Some(size));
+        // the parts are copied one after another, as the server does the work
+        let transfer = async {
+            let mut parts = Vec::new();
+            for (number, start) in (0..size).step_by(part_size as usize).enumerate() {
+                let end = (start + part_size).min(size);
+                parts.push(upload.copy_part(number as u16, &key, start..end).await?);
+                progress_bar.inc(end - start);
+            }
+            upload.complete(&mut parts, String::new()).await
+        };
+        upload.finish_or_abort(false, transfer).await;
+        progress_bar.finish();
+    }
+    // the share is only deleted once its copy is known to be whole
+    let copied = exit_on_error(head_object(config, bucket, client, &new_key).await);
+    if content_length(&copied) != Some(size) {
+        eprintln!(
+            "error: the copy {} is not as large as {}, keeping both",
+            new_key, key
+        );
+        exit(1);
+    }
+    exit_on_error(delete_object(config, bucket, client, &key).await);
+    println!("deleted {}", key);
+
+    let content_type = head
+        .get("content-type")
+        .and_then(|t| t.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let valid_for = link::valid_for(config, &new_key);
+    let mut url = link::share_link(config, bucket, &new_key, file_name, content_type, valid_for);
+    // the key of an encrypted share stays the same
+    if let Some((_, fragment)) = options.share.split_once('#') {
+        url = format!("{}#{}", url, fragment);
+    }
+    println!(
+        "the share expires at {}",
+        humantime::format_rfc3339_seconds(expiry)
+    );
+    println!("\n{}", url);
+    if config.qr {
+        match render_qr_ascii(&url) {
+            Ok(qr) => println!("\n{}", qr),
+            Err(e) => eprintln!("warn: could not generate QR code: {}", e),
+        }
+    }
+}
+
+/// The size of an object, from the `headers` of its `HeadObject` response.
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get("content-length")?.to_str().ok()?.parse().ok()
+}
+
+/// The headers the copy is created with: those of the original object, with
+/// the recorded expiry (if there is one) moved to `expiry`.
+fn copied_headers(head: &HeaderMap, expiry: SystemTime, keep_sse: bool) -> ObjectHeaders {
+    let expires_header = METADATA_PREFIX.to_string() + "expires";
+    let mut headers = ObjectHeaders::new();
+    for (name, value) in head {
+        let name = name.as_str();
+        let kept = KEPT_HEADERS.contains(&name)
+            || name.starts_with(METADATA_PREFIX)
+            || (keep_sse && KEPT_SSE_HEADERS.contains(&name));
+        let Some(value) = value.to_str().ok().filter(|_| kept) else {
+            continue;
+        };
+        let value = match name == expires_header {
+            true => humantime::format_rfc3339_seconds(expiry).to_string(),
+            false => value.to_string(),
+        };
+        headers.push((name.to_string(), value));
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use super::copied_headers;
+
+    #[test]
+    fn copies_keep_their_headers_with_the_new_expiry() {
+        let mut head = HeaderMap::new();
+        for (name, value) in [
+            ("content-type", "text/plain"),
+            ("content-length", "3"),
+            ("etag", "\"abc\""),
+            ("last-modified", "Sat, 17 Oct 2026 10:00:00 GMT"),
+            ("x-amz-meta-expires", "2026-10-24T10:00:00Z"),
+            ("x-amz-meta-project", "apollo"),
+            ("x-amz-storage-class", "STANDARD_IA"),
+            ("x-amz-server-side-encryption", "aws:kms"),
+        ] {
+            head.insert(name, HeaderValue::from_static(value));
+        }
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+
+        let mut headers = copied_headers(&head, expiry, true);
+        headers.sort();
+        let expected = [
+            ("content-type", "text/plain"),
+            ("x-amz-meta-expires", "2027-01-15T08:00:00Z"),
+            ("x-amz-meta-project", "apollo"),
+            ("x-amz-server-side-encryption", "aws:kms"),
+            ("x-amz-storage-class", "STANDARD_IA"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(headers, expected);
+
+        // with --sse, the copy is encrypted as asked instead
+        let headers = copied_headers(&head, expiry, false);
+        assert!(!headers.iter().any(|(name, _)| name.contains("encryption")));
+    }
+}
@@ -0,0 +1,284 @@
+// keys of new uploads and the links that are handed out for them
+
+use std::time::{Duration, SystemTime};
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand_core::{OsRng, RngCore};
+use reqwest::Url;
+use rusty_s3::actions::{GetObject, S3Action};
+use rusty_s3::Bucket;
+
+use crate::config::Config;
+use crate::purge::decode_listed_key;
+use crate::upload::ObjectHeaders;
+use crate::{disposition, sse};
+
+/// Everything but the unreserved characters of RFC 3986 is percent-encoded in
+/// the path of public links.
+pub const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// SigV4 presigned links are valid for at most 7 days.
+pub const MAX_LINK_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// The random part of unguessable keys has 128 bits.
+const RANDOM_PART_BYTES: usize = 16;
+
+/// A new key for `file_name`. It starts with a ULID whose timestamp is
+/// `expiry`, the time the upload expires, which is what `--purge` goes by.
+///
+/// ULIDs are easy to guess, so with `unguessable` a random part follows, as
+/// public links are valid for anyone who knows the key.
+pub fn new_key(expiry: SystemTime, file_name: &str, unguessable: bool) -> String {
+    let ulid = ulid::Ulid::from_datetime(expiry).to_string();
+    if unguessable {
+        let mut random = [0; RANDOM_PART_BYTES];
+        OsRng.fill_bytes(&mut random);
+        format!(
+            "{}/{}/{}",
+            ulid,
+            BASE64_URL_SAFE_NO_PAD.encode(random),
+            file_name
+        )
+    } else {
+        ulid + "/" + file_name
+    }
+}
+
+/// Whether `key` has the random part of [`new_key`], so it may be linked publicly.
+/// Other keys with three segments, like `<ulid>/dir/file`, are not.
+pub fn is_unguessable(key: &str) -> bool {
+    let mut segments = key.splitn(3, '/');
+    let (Some(ulid), Some(random), Some(_)) = (segments.next(), segments.next(), segments.next())
+    else {
+        return false;
+    };
+    // only the 22 characters that 16 bytes are encoded to decode to 16 bytes
+    ulid.parse::<ulid::Ulid>().is_ok()
+        && BASE64_URL_SAFE_NO_PAD
+            .decode(random)
+            .is_ok_and(|random| random.len() == RANDOM_PART_BYTES)
+}
+
+/// When an upload that is made now expires.
+pub fn expiry(config: &Config) -> SystemTime {
+    SystemTime::now() + Duration::from_secs(config.expires.into())
+}
+
+/// How long a link to the earlier upload `key` may be valid: `--link-expires`,
+/// but not beyond the expiry of the upload, after which `--purge` deletes it.
+pub fn valid_for(config: &Config, key: &str) -> Duration {
+    let wanted = Duration::from_secs(config.link_expires.into());
+    match key_expiry(key).and_then(|e| e.duration_since(SystemTime::now()).ok()) {
+        Some(left) => wanted.min(Duration::from_secs(left.as_secs())),
+        None => wanted,
+    }
+}
+
+/// When the upload at `key` expires, going by the ULID at its start.
+/// `None` for keys that were not made by [`new_key`].
+pub fn key_expiry(key: &str) -> Option<SystemTime> {
+    let (ulid, _) = key.split_once('/')?;
+    ulid.parse::<ulid::Ulid>().ok().map(|ulid| ulid.datetime())
+}
+
+/// A presigned link to download `path` as `file_name`, valid for `valid_for`.
+///
+/// The link asks the server to answer with `content_type` and a
+/// content disposition that keeps the original name, also if it is not ASCII.
+/// With `--public`, it is a plain link below the public base URL instead.
+pub fn share_link(
+    config: &Config,
+    bucket: &Bucket,
+    path: &str,
+    file_name: &str,
+    content_type: &str,
+    valid_for: Duration,
+) -> String {
+    if let (true, Some(base_url)) = (config.public, &config.public_base_url) {
+        return public_link(base_url, path);
+    }
+    let mut params = vec![
+        (
+            "response-cache-control".to_string(),
+            "no-cache, no-store".to_string(),
+        ),
+        (
+            "response-content-type".to_string(),
+            content_type.to_string(),
+        ),
+        (
+            "response-content-disposition".to_string(),
+            disposition::content_disposition(file_name, config.inline),
+        ),
+    ];
+    // browsers can not send the SSE-C key as headers, so it goes into the link
+    params.extend(sse::customer_headers(&config.sse));
+    signed_link(config, bucket, path, &params, valid_for)
+}
+
+/// The query parameters of an earlier presigned `link` that ask for response
+/// headers (like `response-content-disposition`) or carry the SSE-C key,
+/// without the ones of its signature. Empty for keys and public links.
+pub fn link_params(link: &str) -> Vec<(String, String)> {
+    match Url::parse(link) {
+        Ok(url) => url
+            .query_pairs()
+            .filter(|(name, _)| !name.starts_with("X-Amz-"))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// A presigned link to `path` with the query parameters `params`, valid for `valid_for`.
+pub fn signed_link(
+    config: &Config,
+    bucket: &Bucket,
+    path: &str,
+    params: &[(String, String)],
+    valid_for: Duration,
+) -> String {
+    let mut action = GetObject::new(bucket, Some(&config.credentials), path);
+    for (name, value) in params {
+        action.query_mut().insert(name, value);
+    }
+    action.sign(valid_for).to_string()
+}
+
+/// A link to `path` below `base_url`, for buckets that can be read by anyone,
+/// or that are behind a CDN.
+pub fn public_link(base_url: &str, path: &str) -> String {
+    let path: Vec<_> = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect();
+    format!("{}/{}", base_url, path.join("/"))
+}
+
+/// The key of a share, given as a link that crab_share printed or as the key
+/// itself. Links are either presigned links into `bucket` or public links
+/// below `public_base_url`; their path is decoded like listed keys are.
+pub fn link_key(
+    bucket: &Bucket,
+    public_base_url: Option<&str>,
+    link: &str,
+) -> Result<String, String> {
+    let mut url = match Url::parse(link) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        // anything else is a key, like `01J.../report.pdf`
+        _ => return Ok(link.trim_start_matches('/').to_string()),
+    };
+    url.set_query(None);
+    url.set_fragment(None);
+    let public_base_url = public_base_url.map(|base| base.to_string() + "/");
+    let path = public_base_url
+        .iter()
+        .chain([&bucket.base_url().to_string()])
+        .find_map(|base| url.as_str().strip_prefix(base.as_str()))
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| format!("{link} is not a link into bucket {}", bucket.name()))?;
+    Ok(decode_listed_key(path.to_string()))
+}
+
+/// Headers for uploads that are linked publicly. Such links can not ask for a
+/// content disposition, so it is stored with the object.
+pub fn object_headers(config: &Config, file_name: &str) -> ObjectHeaders {
+    match config.public {
+        true => vec![(
+            "content-disposition".to_string(),
+            disposition::content_disposition(file_name, config.inline),
+        )],
+        false => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use rusty_s3::{Bucket, UrlStyle};
+
+    use super::{is_unguessable, key_expiry, link_key, link_params, new_key, public_link};
+
+    #[test]
+    fn public_keys_get_a_random_part() {
+        let expiry = SystemTime::now();
+        let key = new_key(expiry, "report.pdf", true);
+        let parts: Vec<_> = key.split('/').collect();
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].len(), 22);
+        assert_eq!(parts[2], "report.pdf");
+        assert_ne!(key, new_key(expiry, "report.pdf", true));
+        assert!(is_unguessable(&key));
+        assert!(!is_unguessable(&new_key(expiry, "report.pdf", false)));
+        // purge still finds the expiry
+        assert!(key_expiry(&key).is_some());
+    }
+
+    #[test]
+    fn other_keys_with_three_segments_are_guessable() {
+        let ulid = ulid::Ulid::from_datetime(SystemTime::now()).to_string();
+        assert!(!is_unguessable(&format!("{ulid}/dir/file")));
+        assert!(!is_unguessable(&format!("{ulid}/{}/file", "*".repeat(22))));
+        assert!(!is_unguessable("dir/AAAAAAAAAAAAAAAAAAAAAA/file"));
+        assert!(is_unguessable(&format!(
+            "{ulid}/AAAAAAAAAAAAAAAAAAAAAA/file"
+        )));
+    }
+
+    #[test]
+    fn public_links_encode_the_key() {
+        assert_eq!(
+            public_link("https://cdn.example.com/shares", "01J/a-b_c/Grüße 1.txt"),
+            "https://cdn.example.com/shares/01J/a-b_c/Gr%C3%BC%C3%9Fe%201.txt"
+        );
+    }
+
+    #[test]
+    fn links_are_turned_back_into_keys() {
+        let key = "01KK3RNR08TGMCJ9E1W0949R1Y/Grüße 1.txt";
+        let endpoint = "https://s3.example.com".parse().unwrap();
+        let path_style = Bucket::new(endpoint, UrlStyle::Path, "bucket", "region").unwrap();
+        let endpoint = "https://s3.example.com".parse().unwrap();
+        let virtual_host =
+            Bucket::new(endpoint, UrlStyle::VirtualHost, "bucket", "region").unwrap();
+        let base_url = "https://cdn.example.com/shares";
+
+        for bucket in [&path_style, &virtual_host] {
+            let link = bucket.object_url(key).unwrap().to_string() + "?X-Amz-Signature=1#secret";
+            assert_eq!(link_key(bucket, None, &link).unwrap(), key);
+        }
+        let link = public_link(base_url, key);
+        assert_eq!(link_key(&path_style, Some(base_url), &link).unwrap(), key);
+        assert_eq!(link_key(&path_style, None, key).unwrap(), key);
+        assert_eq!(
+            link_key(&path_style, None, &format!("/{key}")).unwrap(),
+            key
+        );
+
+        assert!(link_key(&path_style, None, &link).is_err());
+        assert!(link_key(&path_style, None, "https://s3.example.com/other/01J/a.txt").is_err());
+        assert!(link_key(&path_style, None, "https://s3.example.com/bucket/").is_err());
+    }
+
+    #[test]
+    fn relinks_keep_the_response_headers() {
+        let link = "https://s3.example.com/bucket/01J/a.txt?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Expires=60&response-content-type=text%2Fplain&x-amz-server-side-encryption-customer-algorithm=AES256&X-Amz-Signature=abc#key";
+
+        assert_eq!(
+            link_params(link),
+            [
+                ("response-content-type", "text/plain"),
+                ("x-amz-server-side-encryption-customer-algorithm", "AES256"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+        assert!(link_params("01J/a.txt").is_empty());
+        assert!(link_params("https://cdn.example.com/01J/a.txt").is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+// show the shares in the bucket and how long they are still available
+
+use std::io::{self, Write};
+use std::process::exit;
+use std::time::{Duration, SystemTime};
+
+use bytesize::ByteSize;
+use reqwest::Client;
+use rusty_s3::Bucket;
+use serde::Serialize;
+
+use crate::config::{Config, ListFormat, ListOptions, ListSort};
+use crate::link;
+use crate::purge::list_objects;
+
+/// An upload in the bucket.
+#[derive(Debug, PartialEq, Serialize)]
+struct Share {
+    key: String,
+    size: u64,
+    /// RFC 3339
+    expires: String,
+    /// Seconds until the share expires, 0 once it has expired
+    #[serde(rename = "remaining_seconds")]
+    remaining: u64,
+}
+
+/// List the shares in the bucket, that is all objects whose key starts with a
+/// ULID, like [`crate::purge::purge`] does.
+pub async fn list(config: &Config, options: &ListOptions, bucket: &Bucket, client: &Client) {
+    let objects = match list_objects(config, bucket, client).await {
+        Ok(objects) => objects,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    let objects = objects.into_iter().map(|o| (o.key, o.size));
+    let shares = shares(objects, options, SystemTime::now());
+    let written = match options.format {
+        ListFormat::Table => write_table(&shares, io::stdout().lock()),
+        ListFormat::Json => serde_json::to_writer_pretty(io::stdout().lock(), &shares)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(io::stdout())),
+    };
+    if let Err(e) = written {
+        eprintln!("error writing list: {}", e);
+        exit(1);
+    }
+}
+
+/// The shares among `objects` (key and size) that `options` asks for, in its order.
+fn shares(
+    objects: impl Iterator<Item = (String, u64)>,
+    options: &ListOptions,
+    now: SystemTime,
+) -> Vec<Share> {
+    let mut shares: Vec<_> = objects
+        .filter_map(|(key, size)| Some((link::key_expiry(&key)?, key, size)))
+        .map(|(expiry, key, size)| {
+            let remaining = expiry.duration_since(now).unwrap_or_default();
+            (expiry, remaining, key, size)
+        })
+        .filter(|(expiry, _, _, _)| options.all || *expiry > now)
+        .filter(|(_, remaining, _, _)| {
+            options
+                .expiring_within
+                .is_none_or(|within| *remaining <= within)
+        })
+        .collect();
+    match options.sort {
+        ListSort::Expiry => shares.sort_by(|a, b| (a.0, &a.2).cmp(&(b.0, &b.2))),
+        ListSort::Size => shares.sort_by(|a, b| b.3.cmp(&a.3).then_with(|| a.2.cmp(&b.2))),
+        ListSort::Key => shares.sort_by(|a, b| a.2.cmp(&b.2)),
+    }
+    shares
+        .into_iter()
+        .map(|(expiry, remaining, key, size)| Share {
+            key,
+            size,
+            expires: humantime::format_rfc3339_seconds(expiry).to_string(),
+            remaining: remaining.as_secs(),
+        })
+        .collect()
+}
+
+/// `remaining` in its two largest units, like `6d 23h` or `12m 5s`.
+fn format_remaining(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    if secs == 0 {
+        return "expired".to_string();
+    }
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let first = units.iter().position(|(n, _)| *n > 0).unwrap_or(3);
+    units[first..]
+        .iter()
+        .take(2)
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{n}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_table<W: Write>(shares: &[Share], mut writer: W) -> io::Result<()> {
+    let rows: Vec<_> = shares
+        .iter()
+        .map(|share| {
+            [
+                share.key.clone(),
+                ByteSize(share.size).to_string(),
+                share.expires.clone(),
+                format_remaining(Duration::from_secs(share.remaining)),
+            ]
+        })
+        .collect();
+    let header = ["KEY", "SIZE", "EXPIRES", "REMAINING"].map(String::from);
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        writeln!(
+            writer,
+            "{:<w0$}  {:>w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        )?;
+    }
+    let total: u64 = shares.iter().map(|share| share.size).sum();
+    writeln!(writer, "\n{} share(s), {}", shares.len(), ByteSize(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{format_remaining, shares, write_table};
+    use crate::config::{ListFormat, ListOptions, ListSort};
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn options(sort: ListSort) -> ListOptions {
+        ListOptions {
+            sort,
+            expiring_within: None,
+            all: false,
+            format: ListFormat::Table,
+        }
+    }
+
+    /// Objects expiring in 2h, 48h, 1h and an hour ago, and one not made by crab_share.
+    fn objects(now: SystemTime) -> Vec<(String, u64)> {
+        let key = |expiry: SystemTime, name: &str| {
+            format!("{}/{}", ulid::Ulid::from_datetime(expiry), name)
+        };
+        vec![
+            (key(now + 2 * HOUR, "b.txt"), 20),
+            (key(now + 48 * HOUR, "c.txt"), 30),
+            (key(now + HOUR, "a.txt"), 10),
+            (key(now - HOUR, "old.txt"), 40),
+            ("notes/readme.txt".to_string(), 50),
+        ]
+    }
+
+    fn names(shares: &[super::Share]) -> Vec<&str> {
+        shares
+            .iter()
+            .map(|s| s.key.split_once('/').unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn sorts_and_skips_expired_and_foreign_objects() {
+        let now = SystemTime::now();
+        let list = |sort| shares(objects(now).into_iter(), &options(sort), now);
+
+        assert_eq!(names(&list(ListSort::Expiry)), ["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(names(&list(ListSort::Size)), ["c.txt", "b.txt", "a.txt"]);
+        let shares = list(ListSort::Expiry);
+        assert_eq!(shares[0].size, 10);
+        assert!((3590..=3600).contains(&shares[0].remaining));
+    }
+
+    #[test]
+    fn filters_by_remaining_lifetime() {
+        let now = SystemTime::now();
+        let options = ListOptions {
+            expiring_within: Some(24 * HOUR),
+            all: true,
+            ..options(ListSort::Expiry)
+        };
+        let shares = shares(objects(now).into_iter(), &options, now);
+
+        assert_eq!(names(&shares), ["old.txt", "a.txt", "b.txt"]);
+        assert_eq!(shares[0].remaining, 0);
+    }
+
+    #[test]
+    fn formats_remaining_lifetime() {
+        assert_eq!(format_remaining(Duration::ZERO), "expired");
+        assert_eq!(format_remaining(Duration::from_secs(42)), "42s");
+        assert_eq!(format_remaining(Duration::from_secs(12 * 60 + 5)), "12m 5s");
+        assert_eq!(format_remaining(Duration::from_secs(3600 + 7)), "1h");
+        assert_eq!(
+            format_remaining(Duration::from_secs(6 * 86400 + 23 * 3600 + 59 * 60)),
+            "6d 23h"
+        );
+    }
+
+    #[test]
+    fn writes_aligned_table() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let shares = shares(objects(now).into_iter(), &options(ListSort::Key), now);
+        let mut out = Vec::new();
+        write_table(&shares[..2], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("KEY "));
+        assert!(
+            lines[1].ends_with("2027-01-15T09:00:00Z  1h"),
+            "{}",
+            lines[1]
+        );
+        assert_eq!(lines[0].find("EXPIRES"), lines[1].find("2027"));
+        assert_eq!(lines[4], "2 share(s), 30 B");
+    }
+}
@@ -1,22 +1,67 @@
 use bytesize::ByteSize;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::MultiProgress;
 use qrcodegen::{QrCode, QrCodeEcc};
-use std::{fs, process::exit, time::Duration};
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    process::exit,
+    time::{Duration, SystemTime},
+};
 
 use reqwest::Client;
-use rusty_s3::actions::{
-    CompleteMultipartUpload, CreateMultipartUpload, GetObject, PutObject, S3Action,
-};
-use rusty_s3::{Bucket, UrlStyle};
+use rusty_s3::Bucket;
+use sha2::{Digest, Sha256};
 
-use crate::upload_actor::spawn_upload_actor;
+use crate::dedup::Reuse;
+use crate::encrypt::Encryptor;
+use crate::parts::{spawn_part_reader, PartWriter};
+use crate::progress::upload_progress_bar;
+use crate::resume::ResumeState;
+use crate::upload::{MultipartUpload, ObjectHeaders, UploadError, Uploaded};
+use crate::upload_actor::UploadedPart;
 
+mod batch;
+mod checksum;
 mod config;
+mod content_type;
+mod decrypt;
+mod dedup;
+mod delete;
+mod disposition;
+mod encrypt;
+mod extend;
+mod link;
+mod list;
+mod metadata;
+mod parts;
+mod progress;
 mod purge;
+mod relink;
+mod resume;
+mod sse;
+mod upload;
 mod upload_actor;
+mod verify;
 mod zip;
 
 const ONE_HOUR: Duration = Duration::from_secs(3600);
+/// How long connecting to the server may take before the request fails.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Files larger than this are uploaded with a multipart upload.
+const MULTIPART_LIMIT: u64 = 100 * 1024 * 1024;
+
+/// What gets uploaded.
+enum Content {
+    /// A plain file of known size, read from disk part by part
+    File(fs::File, u64),
+    /// Parts of an input that is still being read, like an archive that is
+    /// still being zipped or stdin. The task returns the SHA-256 of the input.
+    Stream(
+        flume::Receiver<(u16, Vec<u8>)>,
+        tokio::task::JoinHandle<Result<String, UploadError>>,
+    ),
+}
 
 fn render_qr_ascii(data: &str) -> Result<String, qrcodegen::DataTooLong> {
     let qr = QrCode::encode_text(data, QrCodeEcc::Low)?;
@@ -43,17 +88,249 @@ fn render_qr_ascii(data: &str) -> Result<String, qrcodegen::DataTooLong> {
     Ok(out)
 }
 
+/// Load the resume state of an interrupted upload of `source` and reconcile
+/// it with the parts the server actually has.
+async fn load_resume_state(
+    config: &config::Config,
+    bucket: &Bucket,
+    client: &Client,
+    source: &Path,
+    file: &fs::File,
+    part_size: usize,
+) -> Option<ResumeState> {
+    let metadata = match file.metadata() {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("error reading file: {}", e);
+            exit(1);
+        }
+    };
+    let mut state = match ResumeState::load(source) {
+        Ok(Some(state)) => state,
+        Ok(None) => return None,
+        Err(e) => {
+            eprintln!("warn: could not read resume state, starting over: {}", e);
+            return None;
+        }
+    };
+    if !state.matches(&metadata, part_size) {
+        eprintln!("warn: file changed since the interrupted upload, starting over");
+        return None;
+    }
+    if !state.checksums.values().all(|c| checksum::is_valid(c)) {
+        eprintln!("warn: resume state has an invalid checksum, starting over");
+        return None;
+    }
+    if config.public && !link::is_unguessable(&state.key) {
+        eprintln!("warn: interrupted upload can not be linked publicly, starting over");
+        return None;
+    }
+    let upload =
+        MultipartUpload::resume(config, bucket, client, &state.key, state.upload_id.clone());
+    let listed = match upload.list_parts().await {
+        Ok(Some(listed)) => listed,
+        Ok(None) => {
+            eprintln!("warn: interrupted upload does not exist anymore, starting over");
+            return None;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    // only trust parts that are complete and whose checksum we know
+    state.parts = listed
+        .into_iter()
+        .filter(|(part_number, _, size)| {
+            *size == state.expected_part_size(*part_number)
+                && state.checksums.contains_key(part_number)
+        })
+        .map(|(part_number, etag, _)| (part_number, etag))
+        .collect();
+    let parts = &state.parts;
+    state
+        .checksums
+        .retain(|part_number, _| parts.contains_key(part_number));
+    println!(
+        "resuming interrupted upload, {} part(s) already uploaded",
+        state.parts.len()
+    );
+    Some(state)
+}
+
+/// Upload the large file `source` part by part, optionally continuing the upload
+/// `resumed`. Returns what was uploaded.
+#[allow(clippy::too_many_arguments)]
+async fn upload_file_multipart(
+    config: &config::Config,
+    bucket: &Bucket,
+    client: &Client,
+    path: &str,
+    headers: &ObjectHeaders,
+    source: &Path,
+    file: fs::File,
+    resumed: Option<ResumeState>,
+    chunk_size: usize,
+    num_threads: usize,
+) -> Uploaded {
+    let metadata = match file.metadata() {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("error reading file: {}", e);
+            exit(1);
+        }
+    };
+    let progress_bar = upload_progress_bar(Some(metadata.len()));
+
+    let upload = match &resumed {
+        Some(state) => {
+            MultipartUpload::resume(config, bucket, client, path, state.upload_id.clone())
+        }
+        None => match MultipartUpload::create(config, bucket, client, path, headers).await {
+            Ok(upload) => upload,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        },
+    };
+    let mut state = match (config.resume, resumed) {
+        (_, Some(state)) => Some(state),
+        (true, None) => Some(ResumeState::new(
+            upload.upload_id().to_string(),
+            path.to_string(),
+            chunk_size,
+            &metadata,
+        )),
+        (false, None) => None,
+    };
+    let save_state = |state: &ResumeState| {
+        if let Err(e) = state.save(source) {
+            eprintln!("warn: could not save resume state: {}", e);
+        }
+    };
+
+    let mut done = Vec::new();
+    if let Some(state) = &state {
+        save_state(state);
+        for (part_number, etag) in &state.parts {
+            progress_bar.inc(state.expected_part_size(*part_number));
+            done.push(UploadedPart {
+                number: *part_number,
+                etag: etag.clone(),
+                checksum: state.checksums[part_number].clone(),
+                size: state.expected_part_size(*part_number),
+            });
+        }
+    }
+    let skip = done.iter().map(|p| p.number).collect();
+
+    let resumable = state.is_some();
+    let (upload_tx, upload_rx) = flume::bounded(num_threads);
+    let reader = spawn_part_reader(file, chunk_size, skip, upload_tx);
+    let transfer = async {
+        let mut parts = upload
+            .upload_parts(upload_rx, num_threads, &progress_bar, |part| {
+                if let Some(state) = &mut state {
+                    state.parts.insert(part.number, part.etag.clone());
+                    state.checksums.insert(part.number, part.checksum.clone());
+                    save_state(state);
+                }
+            })
+            .await?;
+        let sha256 = reader
+            .await
+            .unwrap()
+            .map_err(|e| UploadError::Input(e.to_string()))?;
+        parts.extend(done);
+        upload.complete(&mut parts, sha256).await
+    };
+    let uploaded = upload.finish_or_abort(resumable, transfer).await;
+    progress_bar.finish();
+    if resumable {
+        if let Err(e) = ResumeState::remove(source) {
+            eprintln!("warn: could not remove resume state: {}", e);
+        }
+    }
+    uploaded
+}
+
+/// Upload `body` with a single PUT request and report how long it took.
+async fn put_object(
+    config: &config::Config,
+    bucket: &Bucket,
+    client: &Client,
+    path: &str,
+    headers: &ObjectHeaders,
+    body: Vec<u8>,
+) -> Uploaded {
+    println!("uploading file with single upload");
+    let now = std::time::Instant::now();
+    let content_len = body.len();
+    let progress_bar = upload_progress_bar(Some(content_len as u64));
+    let uploaded = exit_on_error(
+        upload::put_object(config, bucket, client, path, headers, body, &progress_bar).await,
+    );
+    progress_bar.finish();
+    println!(
+        "uploaded file in {:?} ({}/s)",
+        now.elapsed(),
+        ByteSize((content_len as f64 / now.elapsed().as_secs_f64()) as u64)
+    );
+    uploaded
+}
+
+/// Copy `reader` into `writer` on a blocking thread. Returns the SHA-256 of
+/// what was written.
+fn spawn_copy(
+    mut reader: impl Read + Send + 'static,
+    mut writer: PartWriter,
+) -> tokio::task::JoinHandle<Result<String, UploadError>> {
+    tokio::task::spawn_blocking(move || {
+        io::copy(&mut reader, &mut writer)
+            .and_then(|_| writer.finish())
+            .map(|(_, sha256)| sha256)
+            .map_err(|e| UploadError::Input(e.to_string()))
+    })
+}
+
+fn exit_on_error<T>(result: Result<T, UploadError>) -> T {
+    match result {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// The client for all requests. A server that can not be reached fails the
+/// request, so that parts are retried instead of waiting forever.
+fn http_client() -> Client {
+    match Client::builder().connect_timeout(CONNECT_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("error creating http client: {}", e);
+            exit(1);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let client = http_client();
     let config = match config::Config::parse() {
-        Ok(c) => c,
+        Ok(config::Parsed::Config(c)) => *c,
+        Ok(config::Parsed::Decrypt(options)) => {
+            decrypt::decrypt(&options, &client).await;
+            return;
+        }
         Err(e) => {
             eprintln!("{}", e);
             exit(1);
         }
     };
-    let chunk_size = 16 * 1024 * 1024;
-    let num_threads = 8;
+    let num_threads = config.concurrency;
 
     // connect to s3
     let url = match config.url.parse() {
@@ -63,10 +340,9 @@ async fn main() {
             exit(1);
         }
     };
-    let client = Client::new();
     let bucket = match Bucket::new(
         url,
-        UrlStyle::Path,
+        config.url_style,
         config.bucket.clone(),
         config.region.clone(),
     ) {
@@ -78,218 +354,466 @@ async fn main() {
     };
 
     if config.purge {
-        purge::purge(&config, &bucket).await;
+        purge::purge(&config, &bucket, &client).await;
+    }
+
+    if let config::Command::Batch(options) = &config.command {
+        batch::batch(&config, options, &bucket, &client).await;
+        return;
+    }
+
+    if let config::Command::List(options) = &config.command {
+        list::list(&config, options, &bucket, &client).await;
+        return;
+    }
+
+    if let config::Command::Delete(options) = &config.command {
+        delete::delete(&config, options, &bucket, &client).await;
+        return;
+    }
+
+    if let config::Command::Extend(options) = &config.command {
+        extend::extend(&config, options, &bucket, &client).await;
+        return;
+    }
+
+    if let config::Command::Relink(options) = &config.command {
+        relink::relink(&config, options, &bucket, &client).await;
+        return;
     }
 
     // 1. Upload a file to the bucket.
     // <uuid>/filename
 
-    let path = config
-        .path
-        .canonicalize()
-        .unwrap_or_else(|_| panic!("Path could not be canonicalized: {:?}", config.path));
-    let mut file_name = path
-        .file_name()
-        .expect("A canonicalized path should have a file name")
-        .to_string_lossy();
+    // Several paths are bundled into one archive, a single one is uploaded as is.
+    let source = &config.paths[0];
+    let multiple = config.paths.len() > 1;
+    let archive = !config.stdin && !multiple && (source.is_dir() || config.zip_single_file);
+    let mut file_name = if config.stdin {
+        "stdin".to_string()
+    } else if multiple {
+        zip::archive_name(&config.paths)
+    } else {
+        let path = source
+            .canonicalize()
+            .unwrap_or_else(|_| panic!("Path could not be canonicalized: {:?}", source));
+        path.file_name()
+            .expect("A canonicalized path should have a file name")
+            .to_string_lossy()
+            .to_string()
+    };
+    if archive {
+        file_name += ".zip";
+    }
+    let original_name = file_name.clone();
+    if let Some(name) = &config.name {
+        file_name = name.clone();
+    }
 
     // 1.0. Check if file is a directory
-    let content = match (config.path.is_dir(), config.zip_single_file) {
-        (true, _) => {
-            println!("zipping directory...");
-            let src_dir = config.path.to_string_lossy().to_string();
-            file_name = (file_name.to_string() + ".zip").into();
-            match zip::zip_folder(&src_dir, config.compression) {
-                Ok(c) => c,
+    // Directories and zipped files are compressed on a blocking thread and
+    // uploaded while they are being written, plain files are streamed from
+    // disk part by part.
+    // The part size is picked from the size of the input. Archives are a
+    // little larger than their input when they barely compress, and so are
+    // encrypted files. The size of stdin is unknown, so it gets the default
+    // part size.
+    let input_size = match (config.stdin, source.is_dir(), config.zip_single_file) {
+        (true, _, _) => Ok(0),
+        _ if multiple => config
+            .paths
+            .iter()
+            .map(|path| match path.is_dir() {
+                true => fs_extra::dir::get_size(path),
+                false => fs::metadata(path).map(|m| m.len()).map_err(|e| e.into()),
+            })
+            .sum::<Result<u64, _>>()
+            .map(|size| size + size / 100),
+        (_, true, _) => fs_extra::dir::get_size(source).map(|size| size + size / 100),
+        _ if config.zip_single_file || config.encrypt => fs::metadata(source)
+            .map(|m| m.len() + m.len() / 100)
+            .map_err(|e| e.into()),
+        _ => fs::metadata(source).map(|m| m.len()).map_err(|e| e.into()),
+    };
+    let input_size = match input_size {
+        Ok(size) => size,
+        Err(e) => {
+            eprintln!("error getting input size: {}", e);
+            exit(1);
+        }
+    };
+    let chunk_size = match parts::part_size_for(input_size, config.part_size) {
+        Ok(size) => size,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+
+    // Encrypted uploads are encrypted while they are cut into parts. Their
+    // name is encrypted with them and only the key in the link can read it.
+    let key = config.encrypt.then(encrypt::Key::generate);
+    let part_writer = |upload_tx| {
+        let writer = PartWriter::new(chunk_size, upload_tx);
+        match &key {
+            Some(key) => match Encryptor::new(key, &file_name) {
+                Ok(encryptor) => writer.encrypted(encryptor),
                 Err(e) => {
-                    eprintln!("error zipping directory: {}", e);
+                    eprintln!("error: {}", e);
                     exit(1);
                 }
-            }
+            },
+            None => writer,
+        }
+    };
+
+    // Compression and upload progress of archives are shown together.
+    let progress = MultiProgress::new();
+    let content = match (config.stdin, source.is_dir(), config.zip_single_file) {
+        (true, _, _) => {
+            println!("reading from stdin...");
+            let (upload_tx, upload_rx) = flume::bounded(num_threads);
+            Content::Stream(upload_rx, spawn_copy(io::stdin(), part_writer(upload_tx)))
+        }
+        _ if multiple => {
+            println!("zipping {} paths...", config.paths.len());
+            let paths = config.paths.clone();
+            let compression = config.compression;
+            let (upload_tx, upload_rx) = flume::bounded(num_threads);
+            let writer = part_writer(upload_tx);
+            let progress = progress.clone();
+            let zipper = tokio::task::spawn_blocking(move || {
+                zip::zip_paths(&paths, compression, writer, &progress)
+                    .map_err(|e| UploadError::Input(e.to_string()))
+            });
+            Content::Stream(upload_rx, zipper)
         }
-        (_, true) => {
+        (_, true, _) => {
+            println!("zipping directory...");
+            let src_dir = source.to_string_lossy().to_string();
+            let compression = config.compression;
+            let (upload_tx, upload_rx) = flume::bounded(num_threads);
+            let writer = part_writer(upload_tx);
+            let progress = progress.clone();
+            let zipper = tokio::task::spawn_blocking(move || {
+                zip::zip_folder(&src_dir, compression, writer, &progress)
+                    .map_err(|e| UploadError::Input(e.to_string()))
+            });
+            Content::Stream(upload_rx, zipper)
+        }
+        (_, _, true) => {
             println!("zipping file...");
-            let src_dir = config.path.to_string_lossy().to_string();
-            file_name = (file_name.to_string() + ".zip").into();
-            match zip::zip_file(&src_dir, config.compression) {
-                Ok(c) => c,
+            let src_dir = source.to_string_lossy().to_string();
+            let compression = config.compression;
+            let (upload_tx, upload_rx) = flume::bounded(num_threads);
+            let writer = part_writer(upload_tx);
+            let progress = progress.clone();
+            let zipper = tokio::task::spawn_blocking(move || {
+                zip::zip_file(&src_dir, compression, writer, &progress)
+                    .map_err(|e| UploadError::Input(e.to_string()))
+            });
+            Content::Stream(upload_rx, zipper)
+        }
+        _ => {
+            let file = match fs::File::open(source) {
+                Ok(f) => f,
                 Err(e) => {
-                    eprintln!("error zipping file: {}", e);
+                    eprintln!("error reading file: {}", e);
                     exit(1);
                 }
+            };
+            let len = match file.metadata() {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    eprintln!("error reading file: {}", e);
+                    exit(1);
+                }
+            };
+            if key.is_some() {
+                println!("encrypting file...");
+                let (upload_tx, upload_rx) = flume::bounded(num_threads);
+                Content::Stream(upload_rx, spawn_copy(file, part_writer(upload_tx)))
+            } else {
+                Content::File(file, len)
             }
         }
-        _ => match fs::read(&config.path) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("error reading file: {}", e);
-                exit(1);
-            }
-        },
+    };
+    // the server only ever sees an anonymous blob
+    let file_name = match key {
+        Some(_) => encrypt::ENCRYPTED_NAME.to_string(),
+        None => file_name,
+    };
+
+    // The first part of a stream tells its type and whether it needs a
+    // multipart upload at all.
+    let first = match &content {
+        Content::Stream(stream_rx, _) => stream_rx.recv_async().await.ok(),
+        Content::File(..) => None,
     };
 
     // 1.1. Read file
     // 1.2. Create path
-    let ulid = {
-        let expiry = std::time::SystemTime::now() + Duration::from_secs(config.expires.into());
-        ulid::Ulid::from_datetime(expiry).to_string()
+    // An interrupted upload is continued under its original path.
+    let resumed = match &content {
+        Content::File(file, content_len) if config.resume && *content_len > MULTIPART_LIMIT => {
+            load_resume_state(&config, &bucket, &client, source, file, chunk_size).await
+        }
+        _ if config.resume => {
+            eprintln!(
+                "warn: --resume only applies to files larger than {}, uploading without a resume state",
+                ByteSize(MULTIPART_LIMIT)
+            );
+            None
+        }
+        _ => None,
     };
-    let path = ulid + "/" + file_name.as_ref();
-    // 1.3. Upload file to bucket
-    println!(
-        "uploading file with size {} bytes to {}/{}/{} ...",
-        ByteSize(content.len() as u64),
-        config.url,
-        config.bucket,
-        path
-    );
-    if content.len() > 100 * 1024 * 1024 {
-        println!("file too large for simple PUT(> 100MB), uploading with multipart upload");
-        let progress_bar = ProgressBar::new(content.len() as u64);
-        progress_bar.set_style(
-            ProgressStyle::with_template(
-                "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-            )
-            .unwrap()
-            .progress_chars("#>-"));
-
-        let action = CreateMultipartUpload::new(&bucket, Some(&config.credentials), &path);
-
-        let url = action.sign(ONE_HOUR);
-
-        let resp = client.post(url);
-        let resp = match resp.send().await {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("error creating multipart upload: {}", e);
-                exit(1);
-            }
-        };
-        let resp = match resp.error_for_status() {
-            Ok(resp) => resp,
-            Err(e) => {
-                eprintln!("error creating multipart upload: {}", e);
-                exit(1);
-            }
-        };
-
-        let body = match resp.text().await {
-            Ok(b) => b,
-            Err(e) => {
-                eprintln!("error creating multipart upload: {}", e);
-                exit(1);
-            }
-        };
-
-        let upload = match CreateMultipartUpload::parse_response(&body) {
-            Ok(u) => u,
-            Err(e) => {
-                eprintln!("error creating multipart upload: {}", e);
-                exit(1);
-            }
-        };
-
-        let (upload_tx, upload_rx) = flume::bounded(8);
-        let (etag_tx, etag_rx) = flume::unbounded();
-        let runners = (0..num_threads)
-            .map(|_| {
-                let client = client.clone();
-                let bucket = bucket.clone();
-                let config = config.clone();
-                let path = path.clone();
-                let upload = upload.clone();
-                spawn_upload_actor(
-                    config,
-                    bucket,
-                    path,
-                    client,
-                    upload.upload_id().to_string(),
-                    upload_rx.clone(),
-                    etag_tx.clone(),
-                )
-            })
-            .collect::<Vec<_>>();
-        let mut parts = Vec::new();
-        for (i, chunk) in content.chunks(chunk_size).enumerate() {
-            upload_tx.send((i as u16, chunk.to_vec())).unwrap();
-            progress_bar.set_position((i + 1) as u64 * chunk_size as u64);
-        }
-        drop(upload_tx);
-        drop(upload_rx);
-        drop(etag_tx);
-        for runner in runners {
-            runner.await.unwrap();
-        }
-        while let Ok((i, etag)) = etag_rx.recv() {
-            parts.push((i, etag));
-        }
-        drop(etag_rx);
-
-        parts.sort_by_key(|p| p.0);
-
-        let action = CompleteMultipartUpload::new(
-            &bucket,
-            Some(&config.credentials),
-            &path,
-            upload.upload_id(),
-            parts.iter().map(|p| p.1.as_str()),
-        );
-        let url = action.sign(ONE_HOUR);
-
-        let resp = match client.post(url).body(action.body()).send().await {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("error completing multipart upload: {}", e);
-                exit(1);
-            }
-        };
-        match resp.error_for_status() {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("error completing multipart upload: {}", e);
-                exit(1);
-            }
+    // 1.2.1. Link an earlier upload of the same file instead of uploading it
+    // again. The digest is stored with the upload, so later runs can find it.
+    // Files are only hashed up front if an earlier upload has their size,
+    // otherwise (and with --force-upload) the digest is computed while uploading.
+    let candidates = match &content {
+        Content::File(_, content_len) if resumed.is_none() && !config.force_upload => {
+            dedup::candidates_for(&config, &bucket, &client, *content_len).await
         }
-    } else {
-        println!("uploading file with single upload");
-        let now = std::time::Instant::now();
-        let action = PutObject::new(&bucket, Some(&config.credentials), &path);
-        let url = action.sign(ONE_HOUR);
-        let content_len = content.len();
-        let resp = match client.put(url).body(content).send().await {
-            Ok(r) => r,
+        _ => None,
+    };
+    let sha256 = match (&content, &candidates) {
+        (Content::File(file, _), Some(_)) => match dedup::file_sha256(file).await {
+            Ok(sha256) => Some(sha256),
             Err(e) => {
-                eprintln!("error uploading file: {}", e);
+                eprintln!("error reading file: {}", e);
                 exit(1);
             }
-        };
-        match resp.error_for_status() {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("error uploading file: {}", e);
-                exit(1);
+        },
+        _ => None,
+    };
+    let expiry = link::expiry(&config);
+    let reuse = match (&content, &candidates, &sha256) {
+        (Content::File(_, content_len), Some(candidates), Some(sha256)) => {
+            dedup::find_copy(
+                candidates,
+                &config,
+                &bucket,
+                &client,
+                *content_len,
+                sha256,
+                expiry,
+            )
+            .await
+        }
+        _ => None,
+    };
+    let earlier_upload = resumed.is_some() || matches!(reuse, Some(Reuse::Link(_)));
+    let path = match (&resumed, &reuse) {
+        (Some(state), _) => state.key.clone(),
+        (None, Some(Reuse::Link(key))) => key.clone(),
+        (None, _) => link::new_key(expiry, &file_name, config.public),
+    };
+    // Browsers show the file instead of downloading it if they know its type.
+    let content_type = match (&config.content_type, &key) {
+        (_, Some(_)) => encrypt::ENCRYPTED_CONTENT_TYPE.to_string(),
+        (Some(content_type), None) => content_type.clone(),
+        (None, None) => match &content {
+            Content::File(..) => content_type::detect(&file_name, source).to_string(),
+            Content::Stream(..) => {
+                let head = first.as_ref().map(|(_, part)| part.as_slice());
+                content_type::detect_head(&file_name, head.unwrap_or_default()).to_string()
             }
+        },
+    };
+    let mut headers: ObjectHeaders = vec![("content-type".to_string(), content_type.clone())];
+    headers.extend(link::object_headers(&config, &file_name));
+    headers.extend(sha256.as_deref().map(dedup::metadata));
+    // the name of an encrypted upload is as secret as its content
+    headers.extend(metadata::object_headers(
+        &config,
+        &metadata::Recorded {
+            file_name: key.is_none().then_some(original_name.as_str()),
+            size: match &content {
+                Content::File(_, content_len) => Some(*content_len),
+                Content::Stream(..) => None,
+            },
+            expiry,
+        },
+    ));
+    // 1.3. Upload file to bucket
+    let uploaded = match reuse {
+        Some(Reuse::Link(_)) => {
+            println!(
+                "the same file was uploaded before, linking it instead: {}",
+                path
+            );
+            None
         }
-        println!(
-            "uploaded file in {:?} ({}/s)",
-            now.elapsed(),
-            ByteSize((content_len as f64 / now.elapsed().as_secs_f64()) as u64)
-        );
+        // the earlier upload expires too soon, a copy on the server is still
+        // faster than uploading the file again
+        Some(Reuse::Copy(source)) => {
+            println!(
+                "the same file was uploaded before, copying {} to {}",
+                source, path
+            );
+            exit_on_error(
+                upload::copy_object(&config, &bucket, &client, &source, &path, &headers).await,
+            );
+            None
+        }
+        None => {
+            Some(match content {
+                Content::File(file, content_len) => {
+                    println!(
+                        "uploading file with size {} bytes to {}/{}/{} ...",
+                        ByteSize(content_len),
+                        config.url,
+                        config.bucket,
+                        path
+                    );
+                    if content_len > MULTIPART_LIMIT {
+                        println!("file too large for simple PUT(> 100MB), uploading with multipart upload");
+                        upload_file_multipart(
+                            &config,
+                            &bucket,
+                            &client,
+                            &path,
+                            &headers,
+                            source,
+                            file,
+                            resumed,
+                            chunk_size,
+                            num_threads,
+                        )
+                        .await
+                    } else {
+                        let mut body = Vec::with_capacity(content_len as usize);
+                        let mut file = file;
+                        if let Err(e) = file.read_to_end(&mut body) {
+                            eprintln!("error reading file: {}", e);
+                            exit(1);
+                        }
+                        if sha256.is_none() {
+                            headers.push(dedup::metadata(&checksum::hex_digest(
+                                Sha256::new_with_prefix(&body),
+                            )));
+                        }
+                        put_object(&config, &bucket, &client, &path, &headers, body).await
+                    }
+                }
+                Content::Stream(stream_rx, producer) => {
+                    println!(
+                        "uploading {} to {}/{}/{} ...",
+                        match (config.stdin, &key) {
+                            (true, _) => "stdin",
+                            (false, Some(_)) => "encrypted file",
+                            (false, None) => "archive",
+                        },
+                        config.url,
+                        config.bucket,
+                        path
+                    );
+                    // Streams that fit into a single part are uploaded with a simple
+                    // PUT, everything else is streamed into a multipart upload.
+                    let second = match first {
+                        Some(_) => stream_rx.recv_async().await.ok(),
+                        None => None,
+                    };
+                    match (first, second) {
+                        (Some(first), Some(second)) => {
+                            let upload = exit_on_error(
+                                MultipartUpload::create(&config, &bucket, &client, &path, &headers)
+                                    .await,
+                            );
+                            let (upload_tx, upload_rx) = flume::bounded(num_threads);
+                            tokio::spawn(async move {
+                                for part in [first, second] {
+                                    upload_tx.send_async(part).await?;
+                                }
+                                while let Ok(part) = stream_rx.recv_async().await {
+                                    upload_tx.send_async(part).await?;
+                                }
+                                Ok::<_, flume::SendError<_>>(())
+                            });
+                            // the size of the stream is not known until it has ended
+                            let progress_bar = progress.add(upload_progress_bar(None));
+                            let transfer = async {
+                                let mut parts = upload
+                                    .upload_parts(upload_rx, num_threads, &progress_bar, |_| {})
+                                    .await?;
+                                let sha256 = producer.await.unwrap()?;
+                                upload.complete(&mut parts, sha256).await
+                            };
+                            let uploaded = upload.finish_or_abort(false, transfer).await;
+                            progress_bar.finish();
+                            uploaded
+                        }
+                        (first, _) => {
+                            let sha256 = exit_on_error(producer.await.unwrap());
+                            headers.push(dedup::metadata(&sha256));
+                            let body = first.map(|(_, part)| part).unwrap_or_default();
+                            put_object(&config, &bucket, &client, &path, &headers, body).await
+                        }
+                    }
+                }
+            })
+        }
+    };
+
+    // 1.4. Check that the server has what we sent
+    if let (Some(uploaded), true) = (&uploaded, config.verify) {
+        let verified =
+            exit_on_error(verify::verify(&config, &bucket, &client, &path, uploaded).await);
+        println!("verified upload: {}", verified);
     }
+    let sha256 = match uploaded {
+        Some(uploaded) => uploaded.sha256,
+        None => sha256.expect("files are hashed before they are linked or copied again"),
+    };
 
     // 2. Get the url of the file
     // -> presigned url
 
     // 2.1. Create presigned url
-    let mut action = GetObject::new(&bucket, Some(&config.credentials), &path);
-    action
-        .query_mut()
-        .insert("response-cache-control", "no-cache, no-store");
-    let url = action.sign(Duration::from_secs(config.expires.into()));
-    let final_url = url.to_string();
+    // Links to earlier uploads must not outlive them.
+    let valid_for = match earlier_upload {
+        true => link::valid_for(&config, &path),
+        false => Duration::from_secs(config.link_expires.into()),
+    };
+    // ULID timestamps are in milliseconds, a few seconds less are not worth a note
+    let slack = Duration::from_secs(60);
+    if !config.public && valid_for + slack < Duration::from_secs(config.link_expires.into()) {
+        println!(
+            "the upload expires before the requested time, the link is valid for {}",
+            humantime::format_duration(valid_for)
+        );
+    }
+    // presigned links are valid for at most 7 days, uploads may be kept longer
+    let kept_until = link::key_expiry(&path).filter(|e| *e > SystemTime::now() + valid_for + slack);
+    if let (false, Some(kept_until)) = (config.public, kept_until) {
+        println!(
+            "the link is valid for {}, but the upload is kept until {}: get a new link later with `crab_share relink <link>`",
+            humantime::format_duration(valid_for),
+            humantime::format_rfc3339_seconds(kept_until)
+        );
+    }
+    let mut final_url = link::share_link(
+        &config,
+        &bucket,
+        &path,
+        &file_name,
+        &content_type,
+        valid_for,
+    );
+    // browsers never send the fragment, so the key stays with the recipient
+    if let Some(key) = &key {
+        final_url = format!("{}#{}", final_url, key.encode());
+    }
 
     // 2.2. Print url
+    // The digest lets recipients check their download with `sha256sum`. That
+    // of an encrypted upload is the one of the ciphertext, which nobody
+    // downloads, and decrypting it authenticates the content anyway.
     println!("\n{}", final_url);
+    if key.is_none() {
+        println!("sha256: {}", sha256);
+    }
     if config.qr {
         match render_qr_ascii(&final_url) {
             Ok(qr) => println!("\n{}", qr),
@@ -0,0 +1,152 @@
+// storage class and metadata that are stored with an upload
+
+use std::time::SystemTime;
+
+use percent_encoding::utf8_percent_encode;
+
+use crate::config::Config;
+use crate::dedup::SHA256_METADATA;
+use crate::disposition::ATTR_CHAR;
+use crate::upload::ObjectHeaders;
+
+const STORAGE_CLASS_HEADER: &str = "x-amz-storage-class";
+pub const METADATA_PREFIX: &str = "x-amz-meta-";
+/// Objects in these classes have to be restored before they can be downloaded.
+const ARCHIVE_STORAGE_CLASSES: [&str; 2] = ["GLACIER", "DEEP_ARCHIVE"];
+
+/// Check a storage class like `STANDARD_IA`. Classes are upper case, but
+/// `standard_ia` is accepted as well.
+pub fn parse_storage_class(storage_class: &str) -> Result<String, String> {
+    let storage_class = storage_class.to_ascii_uppercase();
+    if storage_class.is_empty()
+        || !storage_class
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("Invalid storage class \"{storage_class}\""));
+    }
+    if ARCHIVE_STORAGE_CLASSES.contains(&storage_class.as_str()) {
+        return Err(format!(
+            "Storage class {storage_class} can not be used, its objects can not be downloaded before they are restored"
+        ));
+    }
+    Ok(storage_class)
+}
+
+/// Split a `--meta key=value` option.
+pub fn split_meta(meta: &str) -> Result<(String, String), String> {
+    meta.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got \"{meta}\""))
+}
+
+/// Check a metadata entry. Keys are lower case and only contain letters, digits,
+/// `-` and `_`; values must be printable ASCII, since they are sent as headers.
+pub fn check_meta((key, value): (String, String)) -> Result<(String, String), String> {
+    let key = key.to_ascii_lowercase();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid metadata key \"{key}\": use letters, digits, '-' and '_'"
+        ));
+    }
+    if METADATA_PREFIX.to_string() + &key == SHA256_METADATA {
+        return Err(format!("Metadata key \"{key}\" is set by crab_share"));
+    }
+    if !value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+        return Err(format!(
+            "Invalid value for metadata key \"{key}\": only printable ASCII is allowed"
+        ));
+    }
+    Ok((key, value))
+}
+
+/// What crab_share records about an upload, unless `--no-default-meta` is given.
+/// Unknown fields are left out.
+pub struct Recorded<'a> {
+    /// The name of the uploaded file, before `--name`. Never recorded for
+    /// encrypted uploads.
+    pub file_name: Option<&'a str>,
+    /// Size of the uploaded file in bytes
+    pub size: Option<u64>,
+    /// When the upload is meant to expire
+    pub expiry: SystemTime,
+}
+
+/// The headers for `PutObject` and `CreateMultipartUpload` with the storage
+/// class, the default metadata and the metadata from `--meta`, which wins over
+/// the defaults.
+pub fn object_headers(config: &Config, recorded: &Recorded) -> ObjectHeaders {
+    let mut metadata = Vec::new();
+    if config.default_meta {
+        if let Some(file_name) = recorded.file_name {
+            // header values are ASCII, so the name is percent-encoded like in
+            // the Content-Disposition
+            let file_name = utf8_percent_encode(file_name, ATTR_CHAR).to_string();
+            metadata.push(("filename".to_string(), file_name));
+        }
+        if let Some(size) = recorded.size {
+            metadata.push(("size".to_string(), size.to_string()));
+        }
+        let hostname = hostname::get().ok().and_then(|h| h.into_string().ok());
+        if let Some(hostname) = hostname.filter(|h| h.chars().all(|c| c.is_ascii_graphic())) {
+            metadata.push(("hostname".to_string(), hostname));
+        }
+        let expires = humantime::format_rfc3339_seconds(recorded.expiry).to_string();
+        metadata.push(("expires".to_string(), expires));
+        metadata.retain(|(key, _)| !config.meta.iter().any(|(k, _)| k == key));
+    }
+    metadata.extend(config.meta.iter().cloned());
+
+    let mut headers: ObjectHeaders = metadata
+        .into_iter()
+        .map(|(key, value)| (METADATA_PREFIX.to_string() + &key, value))
+        .collect();
+    if let Some(storage_class) = &config.storage_class {
+        headers.push((STORAGE_CLASS_HEADER.to_string(), storage_class.clone()));
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_meta, parse_storage_class, split_meta};
+
+    #[test]
+    fn storage_classes_are_upper_case() {
+        assert_eq!(parse_storage_class("standard_ia").unwrap(), "STANDARD_IA");
+        assert_eq!(
+            parse_storage_class("REDUCED_REDUNDANCY").unwrap(),
+            "REDUCED_REDUNDANCY"
+        );
+        assert!(parse_storage_class("").is_err());
+        assert!(parse_storage_class("STANDARD IA").is_err());
+        assert!(parse_storage_class("glacier").is_err());
+    }
+
+    #[test]
+    fn metadata_is_checked() {
+        let meta = |s: &str| split_meta(s).and_then(check_meta);
+
+        assert_eq!(
+            meta("Project=Apollo 11").unwrap(),
+            ("project".to_string(), "Apollo 11".to_string())
+        );
+        assert_eq!(
+            meta("ticket=a=b").unwrap(),
+            ("ticket".to_string(), "a=b".to_string())
+        );
+        assert_eq!(
+            meta("empty=").unwrap(),
+            ("empty".to_string(), String::new())
+        );
+        assert!(meta("no value").is_err());
+        assert!(meta("=value").is_err());
+        assert!(meta("two words=value").is_err());
+        assert!(meta("city=Köln").is_err());
+        assert!(meta("sha256=abc").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use bytesize::ByteSize;
+use sha2::{Digest, Sha256};
+
+use crate::checksum::hex_digest;
+use crate::encrypt::Encryptor;
+
+/// S3 allows at most 10,000 parts per multipart upload.
+pub const MAX_PARTS: u64 = 10_000;
+/// Every part but the last must be at least 5 MiB.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// No part may be larger than 5 GiB.
+pub const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+/// Part size for everything that fits into 10,000 parts of this size.
+const DEFAULT_PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Pick the part size for an upload of `size` bytes, or check the one the user asked for.
+///
+/// Without a `requested` size, parts grow beyond [`DEFAULT_PART_SIZE`] (in steps
+/// of 1 MiB) just enough to stay within [`MAX_PARTS`].
+pub fn part_size_for(size: u64, requested: Option<u64>) -> Result<usize, String> {
+    let needed = size.div_ceil(MAX_PARTS);
+    let part_size = match requested {
+        Some(part_size) => {
+            if !(MIN_PART_SIZE..=MAX_PART_SIZE).contains(&part_size) {
+                return Err(format!(
+                    "part size {} is out of range, it must be between {} and {}",
+                    ByteSize(part_size),
+                    ByteSize(MIN_PART_SIZE),
+                    ByteSize(MAX_PART_SIZE)
+                ));
+            }
+            if part_size < needed {
+                return Err(format!(
+                    "part size {} is too small for {}, it must be at least {} to stay within {} parts",
+                    ByteSize(part_size),
+                    ByteSize(size),
+                    ByteSize(needed),
+                    MAX_PARTS
+                ));
+            }
+            part_size
+        }
+        None => needed.next_multiple_of(1024 * 1024).max(DEFAULT_PART_SIZE),
+    };
+    if part_size > MAX_PART_SIZE {
+        return Err(format!(
+            "{} is too large for a multipart upload (at most {} parts of {})",
+            ByteSize(size),
+            MAX_PARTS,
+            ByteSize(MAX_PART_SIZE)
+        ));
+    }
+    Ok(part_size as usize)
+}
+
+/// Read up to `buf.len()` bytes from `reader`, only stopping early at EOF.
+/// Returns the number of bytes read.
+fn read_part<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Read `reader` in parts of `part_size` bytes on a blocking thread and hand
+/// them to the upload actors. Parts listed in `skip` are already uploaded and
+/// are only read to hash them. Returns the hex encoded SHA-256 of the whole input.
+///
+/// Because `upload_tx` is bounded, at most a few parts are held in memory at
+/// any time, no matter how large the input is.
+pub fn spawn_part_reader<R>(
+    mut reader: R,
+    part_size: usize,
+    skip: BTreeSet<u16>,
+    upload_tx: flume::Sender<(u16, Vec<u8>)>,
+) -> tokio::task::JoinHandle<io::Result<String>>
+where
+    R: Read + Seek + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        reader.seek(SeekFrom::Start(0))?;
+        for part_number in 0.. {
+            let mut chunk = vec![0; part_size];
+            let len = read_part(&mut reader, &mut chunk)?;
+            if len == 0 {
+                break;
+            }
+            chunk.truncate(len);
+            hasher.update(&chunk);
+            if !skip.contains(&part_number) && upload_tx.send((part_number, chunk)).is_err() {
+                // all upload actors are gone, nothing left to do
+                break;
+            }
+            if len < part_size {
+                break;
+            }
+        }
+        Ok(hex_digest(hasher))
+    })
+}
+
+/// A [`Write`] sink that cuts everything written to it into parts of
+/// `part_size` bytes and hands them to the upload actors.
+///
+/// Used for archives that are uploaded while they are still being compressed,
+/// for stdin and for encrypted uploads.
+/// Sending blocks while the upload actors are busy, so this must only be used
+/// from a blocking thread.
+pub struct PartWriter {
+    part_size: usize,
+    next_part: u16,
+    buffer: Vec<u8>,
+    bytes_written: u64,
+    hasher: Sha256,
+    upload_tx: flume::Sender<(u16, Vec<u8>)>,
+    /// Encrypts everything before it is cut into parts
+    encryptor: Option<Encryptor>,
+}
+
+impl PartWriter {
+    pub fn new(part_size: usize, upload_tx: flume::Sender<(u16, Vec<u8>)>) -> Self {
+        Self {
+            part_size,
+            next_part: 0,
+            buffer: Vec::with_capacity(part_size),
+            bytes_written: 0,
+            hasher: Sha256::new(),
+            upload_tx,
+            encryptor: None,
+        }
+    }
+
+    /// Encrypt everything with `encryptor` before it is uploaded. The parts,
+    /// the size and the digest are those of the ciphertext.
+    pub fn encrypted(self, encryptor: Encryptor) -> Self {
+        Self {
+            encryptor: Some(encryptor),
+            ..self
+        }
+    }
+
+    /// Add `data` to the current part, sending every part that is full.
+    fn push(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let len = data.len().min(self.part_size - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..len]);
+            self.hasher.update(&data[..len]);
+            self.bytes_written += len as u64;
+            if self.buffer.len() == self.part_size {
+                self.send_part()?;
+            }
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    fn send_part(&mut self) -> io::Result<()> {
+        if self.next_part as u64 >= MAX_PARTS {
+            return Err(io::Error::other(format!(
+                "input needs more than {} parts, use a larger --part-size",
+                MAX_PARTS
+            )));
+        }
+        let part = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+        self.upload_tx
+            .send((self.next_part, part))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload was cancelled"))?;
+        self.next_part += 1;
+        Ok(())
+    }
+
+    /// Send the last, possibly short, part. Returns the total number of bytes
+    /// written and their hex encoded SHA-256.
+    pub fn finish(mut self) -> io::Result<(u64, String)> {
+        if let Some(encryptor) = self.encryptor.take() {
+            self.push(&encryptor.finish()?)?;
+        }
+        if !self.buffer.is_empty() {
+            self.send_part()?;
+        }
+        Ok((self.bytes_written, hex_digest(self.hasher)))
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encryptor {
+            Some(encryptor) => {
+                encryptor.update(buf)?;
+                let ciphertext = encryptor.take();
+                self.push(&ciphertext)?;
+                Ok(buf.len())
+            }
+            None => {
+                let len = buf.len().min(self.part_size - self.buffer.len());
+                self.push(&buf[..len])?;
+                Ok(len)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::io::{Cursor, Write};
+
+    use super::{part_size_for, read_part, spawn_part_reader, PartWriter, MAX_PARTS};
+    use crate::encrypt::{Decryptor, Encryptor, Key};
+
+    /// A reader that only ever returns a few bytes per call.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn fills_whole_part_from_short_reads() {
+        let mut reader = Trickle(b"0123456789");
+        let mut buf = [0; 8];
+
+        assert_eq!(read_part(&mut reader, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"01234567");
+        assert_eq!(read_part(&mut reader, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"89");
+        assert_eq!(read_part(&mut reader, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn part_writer_cuts_fixed_size_parts() {
+        let (tx, rx) = flume::unbounded();
+        let mut writer = PartWriter::new(4, tx);
+
+        writer.write_all(b"0123456789").unwrap();
+        let (len, sha256) = writer.finish().unwrap();
+        assert_eq!(len, 10);
+        assert_eq!(
+            sha256,
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+
+        let parts: Vec<_> = rx.drain().collect();
+        assert_eq!(
+            parts,
+            vec![
+                (0, b"0123".to_vec()),
+                (1, b"4567".to_vec()),
+                (2, b"89".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn part_writer_encrypts_before_cutting_parts() {
+        let key = Key::generate();
+        let (tx, rx) = flume::unbounded();
+        let mut writer = PartWriter::new(4096, tx).encrypted(Encryptor::new(&key, "a").unwrap());
+
+        let content = vec![3; 100_000];
+        writer.write_all(&content).unwrap();
+        let (len, _) = writer.finish().unwrap();
+
+        let parts: Vec<_> = rx.drain().collect();
+        let ciphertext: Vec<u8> = parts.iter().flat_map(|(_, part)| part.clone()).collect();
+        assert_eq!(ciphertext.len() as u64, len);
+        assert!(parts
+            .iter()
+            .rev()
+            .skip(1)
+            .all(|(_, part)| part.len() == 4096));
+        let mut decryptor = Decryptor::new(key);
+        let mut plaintext = decryptor.update(&ciphertext).unwrap();
+        plaintext.extend(decryptor.finish().unwrap());
+        assert_eq!(plaintext, content);
+    }
+
+    #[tokio::test]
+    async fn part_reader_skips_uploaded_parts() {
+        let (tx, rx) = flume::unbounded();
+        let reader = spawn_part_reader(
+            Cursor::new(b"0123456789".to_vec()),
+            4,
+            BTreeSet::from([0, 2]),
+            tx,
+        );
+        let sha256 = reader.await.unwrap().unwrap();
+
+        let parts: Vec<_> = rx.drain().collect();
+        assert_eq!(parts, vec![(1, b"4567".to_vec())]);
+        // skipped parts are part of the digest nonetheless
+        assert_eq!(
+            sha256,
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+    }
+
+    #[test]
+    fn part_size_grows_with_the_input() {
+        const MIB: u64 = 1024 * 1024;
+
+        assert_eq!(part_size_for(0, None).unwrap() as u64, 16 * MIB);
+        assert_eq!(part_size_for(100_000 * MIB, None).unwrap() as u64, 16 * MIB);
+        // 200 GiB needs parts of at least 20.48 MiB
+        assert_eq!(
+            part_size_for(200 * 1024 * MIB, None).unwrap() as u64,
+            21 * MIB
+        );
+        assert!(
+            part_size_for(200 * 1024 * MIB, None).unwrap() as u64 * MAX_PARTS >= 200 * 1024 * MIB
+        );
+        assert!(part_size_for(50 * 1024 * 1024 * MIB, None).is_err());
+    }
+
+    #[test]
+    fn requested_part_size_is_validated() {
+        const MIB: u64 = 1024 * 1024;
+
+        assert_eq!(part_size_for(MIB, Some(64 * MIB)).unwrap() as u64, 64 * MIB);
+        assert!(part_size_for(MIB, Some(MIB)).is_err());
+        assert!(part_size_for(MIB, Some(6 * 1024 * MIB)).is_err());
+        assert!(part_size_for(200 * 1024 * MIB, Some(16 * MIB)).is_err());
+    }
+}
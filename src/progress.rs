@@ -0,0 +1,127 @@
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Bytes handed to the connection at once. Small enough for a smooth progress bar.
+const FRAME_SIZE: usize = 64 * 1024;
+
+/// A progress bar for an upload of `len` bytes, or of unknown size.
+pub fn upload_progress_bar(len: Option<u64>) -> ProgressBar {
+    match len {
+        Some(len) => {
+            let progress_bar = ProgressBar::new(len);
+            progress_bar.set_style(
+                ProgressStyle::with_template(
+                    "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+                )
+                .unwrap()
+                .progress_chars("#>-"));
+            progress_bar
+        }
+        None => {
+            let progress_bar = ProgressBar::no_length();
+            progress_bar.set_style(
+                ProgressStyle::with_template(
+                    "{spinner:.green} [{elapsed_precise}] {bytes} uploaded ({bytes_per_sec})",
+                )
+                .unwrap(),
+            );
+            progress_bar
+        }
+    }
+}
+
+/// A progress bar for a download of `len` bytes, or of unknown size.
+pub fn download_progress_bar(len: Option<u64>) -> ProgressBar {
+    match len {
+        Some(len) => upload_progress_bar(Some(len)),
+        None => {
+            let progress_bar = ProgressBar::no_length();
+            progress_bar.set_style(
+                ProgressStyle::with_template(
+                    "{spinner:.green} [{elapsed_precise}] {bytes} downloaded ({bytes_per_sec})",
+                )
+                .unwrap(),
+            );
+            progress_bar
+        }
+    }
+}
+
+/// A request body that advances a progress bar as it is actually sent, not
+/// when it is queued.
+pub struct ProgressBody {
+    data: Bytes,
+    progress_bar: ProgressBar,
+    sent: Arc<AtomicU64>,
+}
+
+impl ProgressBody {
+    /// Returns the body and a counter of the bytes it has sent so far.
+    pub fn new(data: Bytes, progress_bar: ProgressBar) -> (Self, Arc<AtomicU64>) {
+        let sent = Arc::new(AtomicU64::new(0));
+        let body = Self {
+            data,
+            progress_bar,
+            sent: sent.clone(),
+        };
+        (body, sent)
+    }
+}
+
+impl Body for ProgressBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if self.data.is_empty() {
+            return Poll::Ready(None);
+        }
+        let len = self.data.len().min(FRAME_SIZE);
+        let frame = self.data.split_to(len);
+        self.progress_bar.inc(len as u64);
+        self.sent.fetch_add(len as u64, Ordering::Relaxed);
+        Poll::Ready(Some(Ok(Frame::data(frame))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use indicatif::ProgressBar;
+
+    use super::ProgressBody;
+
+    #[tokio::test]
+    async fn counts_sent_bytes() {
+        let progress_bar = ProgressBar::hidden();
+        let data = Bytes::from(vec![7; 200 * 1024]);
+        let (body, sent) = ProgressBody::new(data.clone(), progress_bar.clone());
+
+        let collected = body.collect().await.unwrap().to_bytes();
+
+        assert_eq!(collected, data);
+        assert_eq!(sent.load(Ordering::Relaxed), 200 * 1024);
+        assert_eq!(progress_bar.position(), 200 * 1024);
+    }
+}
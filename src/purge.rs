@@ -1,141 +1,107 @@
 // list bucket and filter out files that are expired
 
 use std::process::exit;
+use std::time::SystemTime;
 
 use percent_encoding::percent_decode_str;
 use reqwest::Client;
-use rusty_s3::actions::{ListObjectsV2, S3Action};
+use rusty_s3::actions::list_objects_v2::ListObjectsContent;
+use rusty_s3::actions::{DeleteObject, ListObjectsV2, S3Action};
 use rusty_s3::Bucket;
 
+use crate::config::Config;
+use crate::link;
+use crate::upload::{send, UploadError};
 use crate::ONE_HOUR;
 
-pub async fn purge(config: &crate::config::Config, bucket: &Bucket) {
-    let client = Client::new();
+/// List all objects in the bucket, with decoded keys.
+pub async fn list_objects(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+) -> Result<Vec<ListObjectsContent>, UploadError> {
+    list_objects_below(config, bucket, client, "").await
+}
 
+/// List the objects whose keys start with `prefix`, with decoded keys.
+pub async fn list_objects_below(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    prefix: &str,
+) -> Result<Vec<ListObjectsContent>, UploadError> {
+    const WHAT: &str = "listing bucket";
     let mut action = ListObjectsV2::new(bucket, Some(&config.credentials));
-    let url: reqwest::Url = action.sign(ONE_HOUR);
-    let resp = match client.get(url).send().await {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("error listing bucket: {}", e);
-            exit(1);
-        }
-    };
-    let resp = match resp.error_for_status() {
-        Ok(resp) => resp,
-        Err(e) => {
-            eprintln!("error listing bucket: {}", e);
-            exit(1);
-        }
-    };
-    let resp = match resp.text().await {
-        Ok(resp) => resp,
-        Err(e) => {
-            eprintln!("error listing bucket: {}", e);
-            exit(1);
+    if !prefix.is_empty() {
+        action.with_prefix(prefix);
+    }
+    let mut files = Vec::new();
+    loop {
+        let url: reqwest::Url = action.sign(ONE_HOUR);
+        let resp = send(client.get(url), WHAT).await?;
+        let resp = resp
+            .text()
+            .await
+            .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+        let resp = ListObjectsV2::parse_response(&resp)
+            .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+        files.extend(resp.contents);
+        match resp.next_continuation_token {
+            Some(token) => {
+                action.query_mut().insert("continuation-token", token);
+            }
+            None => break,
         }
-    };
-    let resp = match ListObjectsV2::parse_response(&resp) {
-        Ok(resp) => resp,
+    }
+    // ListObjectsV2 requests encoding-type=url, so returned keys must be
+    // decoded before they are passed to other actions like DeleteObject.
+    for file in &mut files {
+        file.key = decode_listed_key(std::mem::take(&mut file.key));
+    }
+    Ok(files)
+}
+
+pub async fn purge(config: &Config, bucket: &Bucket, client: &Client) {
+    let files = match list_objects(config, bucket, client).await {
+        Ok(files) => files,
         Err(e) => {
-            eprintln!("error listing bucket: {}", e);
+            eprintln!("{}", e);
             exit(1);
         }
     };
-    let mut files = resp.contents;
-    let mut continuation_token = resp.next_continuation_token;
-
-    while let Some(token) = continuation_token.take() {
-        action.query_mut().insert("continuation-token", token);
-        let url: reqwest::Url = action.sign(ONE_HOUR);
-        let resp = match client.get(url).send().await {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("error listing bucket: {}", e);
-                exit(1);
-            }
-        };
-        let resp = match resp.error_for_status() {
-            Ok(resp) => resp,
-            Err(e) => {
-                eprintln!("error listing bucket: {}", e);
-                exit(1);
-            }
-        };
-        let resp = match resp.text().await {
-            Ok(resp) => resp,
-            Err(e) => {
-                eprintln!("error listing bucket: {}", e);
-                exit(1);
-            }
-        };
-        let resp = match ListObjectsV2::parse_response(&resp) {
-            Ok(resp) => resp,
-            Err(e) => {
-                eprintln!("error listing bucket: {}", e);
-                exit(1);
-            }
-        };
-        files.extend(resp.contents);
-        continuation_token = resp.next_continuation_token;
-    }
-    // extract only the key from each file
-    let files: Vec<_> = files
+    let now = SystemTime::now();
+    let files_to_delete: Vec<_> = files
         .into_iter()
-        // ListObjectsV2 requests encoding-type=url, so returned keys must be
-        // decoded before they are passed to DeleteObject.
-        .map(|f| decode_listed_key(f.key))
-        // decode the timestamp from the uuid
-        .flat_map(|k| {
-            let mut parts = k.split('/');
-            // first part is the uuid
-            let ulid = match parts.next() {
-                Some(ulid) => ulid,
-                None => return None,
-            };
-            let ulid = match ulid.parse::<ulid::Ulid>() {
-                Ok(ulid) => ulid,
-                Err(_) => return None,
-            };
-            let expiry_timestamp = ulid.timestamp_ms();
-            Some((k, expiry_timestamp))
-        })
+        .map(|f| f.key)
+        // the ulid at the start of the key tells when the file expires
+        .filter(|key| link::key_expiry(key).is_some_and(|expiry| expiry < now))
         .collect();
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
-
-    let mut files_to_delete = vec![];
-    for file in files {
-        let (key, timestamp) = file;
-        if timestamp < now {
-            files_to_delete.push(key);
-        }
-    }
 
     for file in files_to_delete {
-        let action = rusty_s3::actions::DeleteObject::new(bucket, Some(&config.credentials), &file);
-        let url = action.sign(ONE_HOUR);
-        let resp = match client.delete(url).send().await {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("error deleting file: {}", e);
-                exit(1);
-            }
-        };
-        let _resp = match resp.error_for_status() {
-            Ok(resp) => resp,
-            Err(e) => {
-                eprintln!("error deleting file: {}", e);
-                exit(1);
-            }
-        };
+        if let Err(e) = delete_object(config, bucket, client, &file).await {
+            eprintln!("{}", e);
+            exit(1);
+        }
         println!("deleted expired file: {}", file);
     }
 }
 
-fn decode_listed_key(key: String) -> String {
+/// Delete the object at `key`.
+pub async fn delete_object(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    key: &str,
+) -> Result<(), UploadError> {
+    let action = DeleteObject::new(bucket, Some(&config.credentials), key);
+    let url = action.sign(ONE_HOUR);
+    send(client.delete(url), "deleting file").await?;
+    Ok(())
+}
+
+/// Decode a key that is percent-encoded, like the ones `ListObjectsV2` returns
+/// or the path of a link. Keys that are not valid UTF-8 once decoded are kept.
+pub fn decode_listed_key(key: String) -> String {
     percent_decode_str(&key)
         .decode_utf8()
         .map(|decoded| decoded.into_owned())
@@ -0,0 +1,104 @@
+// print a new link to an object whose link expired
+
+use std::process::exit;
+use std::time::{Duration, SystemTime};
+
+use reqwest::Client;
+use rusty_s3::Bucket;
+
+use crate::config::{Config, RelinkOptions};
+use crate::upload::head_object;
+use crate::{exit_on_error, link, render_qr_ascii};
+
+/// Print a new presigned link to the object in `options`. A link that is
+/// given is signed again with the same response headers, like the content
+/// disposition; for a key, the link is made like the one of a new upload.
+///
+/// Objects that crab_share did not upload work as well, they just have no
+/// expiry to keep the link within.
+pub async fn relink(config: &Config, options: &RelinkOptions, bucket: &Bucket, client: &Client) {
+    let key = match link::link_key(bucket, config.public_base_url.as_deref(), &options.share) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+    let wanted = Duration::from_secs(config.link_expires.into());
+    let valid_for = match lifetime(wanted, &key, SystemTime::now()) {
+        Ok(valid_for) => valid_for,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+    let head = exit_on_error(head_object(config, bucket, client, &key).await);
+
+    let params = link::link_params(&options.share);
+    let mut url = if params.is_empty() {
+        let content_type = head
+            .get("content-type")
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or("application/octet-stream");
+        let file_name = key.rsplit('/').next().unwrap_or(&key);
+        link::share_link(config, bucket, &key, file_name, content_type, valid_for)
+    } else {
+        link::signed_link(config, bucket, &key, &params, valid_for)
+    };
+    // the key of an encrypted share stays the same
+    if let Some((_, fragment)) = options.share.split_once('#') {
+        url = format!("{}#{}", url, fragment);
+    }
+    if !config.public {
+        println!(
+            "the link is valid for {}",
+            humantime::format_duration(valid_for)
+        );
+    }
+    println!("\n{}", url);
+    if config.qr {
+        match render_qr_ascii(&url) {
+            Ok(qr) => println!("\n{}", qr),
+            Err(e) => eprintln!("warn: could not generate QR code: {}", e),
+        }
+    }
+}
+
+/// How long a new link to `key` is valid: `wanted`, but neither beyond the
+/// expiry of the share nor beyond what a presigned link allows.
+fn lifetime(wanted: Duration, key: &str, now: SystemTime) -> Result<Duration, String> {
+    let wanted = wanted.min(link::MAX_LINK_LIFETIME);
+    let Some(expiry) = link::key_expiry(key) else {
+        return Ok(wanted);
+    };
+    match expiry.duration_since(now) {
+        Ok(left) => Ok(wanted.min(Duration::from_secs(left.as_secs()))),
+        Err(_) => Err(format!(
+            "the share expired at {}, keep it for longer with `crab_share extend` first",
+            humantime::format_rfc3339_seconds(expiry)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::lifetime;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn links_end_with_the_share_or_the_signing_maximum() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let key = |expiry| format!("{}/a.txt", ulid::Ulid::from_datetime(expiry));
+
+        assert_eq!(lifetime(7 * DAY, &key(now + 2 * DAY), now), Ok(2 * DAY));
+        assert_eq!(lifetime(7 * DAY, &key(now + 30 * DAY), now), Ok(7 * DAY));
+        assert_eq!(lifetime(30 * DAY, &key(now + 30 * DAY), now), Ok(7 * DAY));
+        assert_eq!(lifetime(DAY, &key(now + 30 * DAY), now), Ok(DAY));
+        assert!(lifetime(7 * DAY, &key(now - DAY), now).is_err());
+        // objects that crab_share did not upload
+        assert_eq!(lifetime(30 * DAY, "reports/a.txt", now), Ok(7 * DAY));
+    }
+}
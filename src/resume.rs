@@ -0,0 +1,155 @@
+// keep track of multipart uploads, so they can be resumed after the process died
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+/// Everything needed to continue an interrupted multipart upload of a file.
+///
+/// Stored as `<file>.crab_share.json` next to the uploaded file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResumeState {
+    #[serde(rename = "uploadId")]
+    pub upload_id: String,
+    pub key: String,
+    #[serde(rename = "partSize")]
+    pub part_size: usize,
+    /// Size of the source file, to detect changes since the upload was started
+    #[serde(rename = "fileSize")]
+    pub file_size: u64,
+    /// Modification time of the source file in seconds since the epoch
+    pub modified: u64,
+    /// ETags of the parts that are already uploaded, by part number (starting at 0)
+    pub parts: BTreeMap<u16, String>,
+    /// Base64 encoded SHA-256 of the parts that are already uploaded, by part number
+    #[serde(default)]
+    pub checksums: BTreeMap<u16, String>,
+}
+
+impl ResumeState {
+    pub fn new(
+        upload_id: String,
+        key: String,
+        part_size: usize,
+        source: &fs::Metadata,
+    ) -> ResumeState {
+        ResumeState {
+            upload_id,
+            key,
+            part_size,
+            file_size: source.len(),
+            modified: modified_secs(source),
+            parts: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+        }
+    }
+
+    /// Where the state for `source` is stored.
+    pub fn file_for(source: &Path) -> PathBuf {
+        let mut name = source.as_os_str().to_owned();
+        name.push(".crab_share.json");
+        PathBuf::from(name)
+    }
+
+    /// Load the state for `source`, if there is one.
+    pub fn load(source: &Path) -> io::Result<Option<ResumeState>> {
+        let content = match fs::read_to_string(Self::file_for(source)) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, source: &Path) -> io::Result<()> {
+        let file = Self::file_for(source);
+        // write to a temporary file first, so a crash never leaves a truncated state behind
+        let mut tmp = file.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &file)
+    }
+
+    pub fn remove(source: &Path) -> io::Result<()> {
+        match fs::remove_file(Self::file_for(source)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the state still belongs to `source`, i.e. the file has not changed since.
+    pub fn matches(&self, source: &fs::Metadata, part_size: usize) -> bool {
+        self.file_size == source.len()
+            && self.modified == modified_secs(source)
+            && self.part_size == part_size
+    }
+
+    /// Size of the given part (starting at 0) of the source file.
+    pub fn expected_part_size(&self, part_number: u16) -> u64 {
+        let start = part_number as u64 * self.part_size as u64;
+        self.file_size
+            .saturating_sub(start)
+            .min(self.part_size as u64)
+    }
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use super::ResumeState;
+
+    fn state() -> ResumeState {
+        ResumeState {
+            upload_id: "upload".to_string(),
+            key: "01KK3RNR08TGMCJ9E1W0949R1Y/file.bin".to_string(),
+            part_size: 10,
+            file_size: 25,
+            modified: 0,
+            parts: BTreeMap::from([(0, "\"etag\"".to_string())]),
+            checksums: BTreeMap::from([(0, "c2hh".to_string())]),
+        }
+    }
+
+    #[test]
+    fn state_file_is_next_to_source() {
+        assert_eq!(
+            ResumeState::file_for(Path::new("/tmp/some dir/file.bin")),
+            Path::new("/tmp/some dir/file.bin.crab_share.json")
+        );
+    }
+
+    #[test]
+    fn last_part_is_shorter() {
+        let state = state();
+
+        assert_eq!(state.expected_part_size(0), 10);
+        assert_eq!(state.expected_part_size(1), 10);
+        assert_eq!(state.expected_part_size(2), 5);
+        assert_eq!(state.expected_part_size(3), 0);
+    }
+
+    #[test]
+    fn roundtrips_through_json() {
+        let state = state();
+        let json = serde_json::to_string(&state).unwrap();
+
+        assert_eq!(serde_json::from_str::<ResumeState>(&json).unwrap(), state);
+    }
+}
@@ -0,0 +1,283 @@
+// server-side encryption of uploads
+
+use std::fmt::{self, Display, Formatter};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use md5::{Digest, Md5};
+use reqwest::header::HeaderMap;
+
+use crate::upload::ObjectHeaders;
+
+const SSE_HEADER: &str = "x-amz-server-side-encryption";
+const KMS_KEY_ID_HEADER: &str = "x-amz-server-side-encryption-aws-kms-key-id";
+const CUSTOMER_ALGORITHM_HEADER: &str = "x-amz-server-side-encryption-customer-algorithm";
+const CUSTOMER_KEY_HEADER: &str = "x-amz-server-side-encryption-customer-key";
+const CUSTOMER_KEY_MD5_HEADER: &str = "x-amz-server-side-encryption-customer-key-MD5";
+
+/// How the server encrypts uploads at rest.
+#[derive(Clone, PartialEq)]
+pub enum Sse {
+    /// With keys managed by S3 (SSE-S3)
+    S3,
+    /// With a key in AWS KMS, or the default KMS key of the bucket (SSE-KMS)
+    Kms(Option<String>),
+    /// With a 256 bit key that is sent with every request (SSE-C)
+    Customer(Vec<u8>),
+}
+
+impl Sse {
+    /// Parse `aes256`, `kms`, `kms:<key-id>` or `customer`, in any case.
+    /// `customer` needs `customer_key`, a base64 encoded 256 bit key.
+    pub fn parse(sse: &str, customer_key: Option<&str>) -> Result<Self, String> {
+        let (mode, key_id) = match sse.split_once(':') {
+            Some((mode, key_id)) => (mode, Some(key_id)),
+            None => (sse, None),
+        };
+        match (mode.to_ascii_lowercase().as_str(), key_id) {
+            ("kms", Some(key_id)) if !key_id.is_empty() => Ok(Sse::Kms(Some(key_id.to_string()))),
+            ("aes256", None) => Ok(Sse::S3),
+            ("kms", None) => Ok(Sse::Kms(None)),
+            ("customer", None) => {
+                let key = customer_key
+                    .ok_or("--sse customer needs a key, set it with --sse-customer-key")?;
+                let key = BASE64_STANDARD
+                    .decode(key.trim())
+                    .map_err(|e| format!("the SSE-C key is not valid base64: {e}"))?;
+                if key.len() != 32 {
+                    return Err(format!(
+                        "the SSE-C key must be 32 bytes long, not {}",
+                        key.len()
+                    ));
+                }
+                Ok(Sse::Customer(key))
+            }
+            _ => Err(format!(
+                "invalid --sse \"{sse}\", expected aes256, kms, kms:<key-id> or customer"
+            )),
+        }
+    }
+
+    /// Headers for the requests that create an object: `PutObject` and
+    /// `CreateMultipartUpload`.
+    pub fn object_headers(&self) -> ObjectHeaders {
+        match self {
+            Sse::S3 => vec![(SSE_HEADER.to_string(), "AES256".to_string())],
+            Sse::Kms(key_id) => {
+                let mut headers = vec![(SSE_HEADER.to_string(), "aws:kms".to_string())];
+                if let Some(key_id) = key_id {
+                    headers.push((KMS_KEY_ID_HEADER.to_string(), key_id.clone()));
+                }
+                headers
+            }
+            Sse::Customer(_) => self.customer_headers(),
+        }
+    }
+
+    /// Headers for every request that reads or writes the data of an object,
+    /// like `UploadPart`, `HeadObject` and `GetObject`. Only SSE-C needs them.
+    pub fn customer_headers(&self) -> ObjectHeaders {
+        match self {
+            Sse::Customer(key) => vec![
+                (CUSTOMER_ALGORITHM_HEADER.to_string(), "AES256".to_string()),
+                (CUSTOMER_KEY_HEADER.to_string(), BASE64_STANDARD.encode(key)),
+                (
+                    CUSTOMER_KEY_MD5_HEADER.to_string(),
+                    BASE64_STANDARD.encode(Md5::digest(key)),
+                ),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether an object is encrypted like this, judging by the `headers` of
+    /// its `HeadObject` response. S3 reports KMS keys by their ARN, so a key
+    /// given by its id matches the end of it, one given by an alias never does.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        match self {
+            Sse::S3 => header(SSE_HEADER) == Some("AES256"),
+            Sse::Kms(key_id) => {
+                header(SSE_HEADER) == Some("aws:kms")
+                    && key_id.as_ref().is_none_or(|key_id| {
+                        header(KMS_KEY_ID_HEADER).is_some_and(|stored| {
+                            stored == key_id || stored.ends_with(&format!("/{key_id}"))
+                        })
+                    })
+            }
+            Sse::Customer(key) => {
+                header(CUSTOMER_KEY_MD5_HEADER)
+                    == Some(BASE64_STANDARD.encode(Md5::digest(key)).as_str())
+            }
+        }
+    }
+}
+
+/// Never print the customer key.
+impl fmt::Debug for Sse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for Sse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Sse::S3 => write!(f, "aes256"),
+            Sse::Kms(None) => write!(f, "kms"),
+            Sse::Kms(Some(key_id)) => write!(f, "kms:{key_id}"),
+            Sse::Customer(_) => write!(f, "customer"),
+        }
+    }
+}
+
+/// Headers for the requests that create an object with `sse`.
+pub fn object_headers(sse: &Option<Sse>) -> ObjectHeaders {
+    sse.as_ref().map(Sse::object_headers).unwrap_or_default()
+}
+
+/// Headers for the requests that read or write the data of an object with `sse`.
+pub fn customer_headers(sse: &Option<Sse>) -> ObjectHeaders {
+    sse.as_ref().map(Sse::customer_headers).unwrap_or_default()
+}
+
+/// Whether an object with the `headers` of its `HeadObject` response is
+/// encrypted with `sse`. Without `sse` the bucket default applies, and any
+/// object matches.
+pub fn matches(sse: &Option<Sse>, headers: &HeaderMap) -> bool {
+    sse.as_ref().is_none_or(|sse| sse.matches(headers))
+}
+
+/// Headers to read the source of a copy that is encrypted with `sse`. Only
+/// SSE-C needs them.
+pub fn copy_source_headers(sse: &Option<Sse>) -> ObjectHeaders {
+    customer_headers(sse)
+        .into_iter()
+        .map(|(name, value)| (name.replacen("x-amz-", "x-amz-copy-source-", 1), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderMap;
+
+    use super::{copy_source_headers, matches, Sse};
+
+    /// 32 bytes of 0x01, base64 encoded
+    const KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(Sse::parse("aes256", None), Ok(Sse::S3));
+        assert_eq!(Sse::parse("AES256", None), Ok(Sse::S3));
+        assert_eq!(Sse::parse("kms", None), Ok(Sse::Kms(None)));
+        assert_eq!(Sse::parse("KMS", None), Ok(Sse::Kms(None)));
+        assert_eq!(
+            Sse::parse("KMS:alias/Shares", None),
+            Ok(Sse::Kms(Some("alias/Shares".to_string())))
+        );
+        assert_eq!(
+            Sse::parse("kms:arn:aws:kms:eu-central-1:1234:key/abcd", None),
+            Ok(Sse::Kms(Some(
+                "arn:aws:kms:eu-central-1:1234:key/abcd".to_string()
+            )))
+        );
+        assert_eq!(
+            Sse::parse("customer", Some(KEY)),
+            Ok(Sse::Customer(vec![1; 32]))
+        );
+        assert_eq!(
+            Sse::parse("Customer", Some(KEY)),
+            Ok(Sse::Customer(vec![1; 32]))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_modes_and_keys() {
+        assert!(Sse::parse("des", None).is_err());
+        assert!(Sse::parse("kms:", None).is_err());
+        assert!(Sse::parse("aes256:key", None).is_err());
+        assert!(Sse::parse("customer", None).is_err());
+        assert!(Sse::parse("customer", Some("not base64!")).is_err());
+        assert!(Sse::parse("customer", Some("AQEBAQ==")).is_err());
+    }
+
+    #[test]
+    fn customer_headers_carry_key_and_md5() {
+        let sse = Sse::Customer(vec![1; 32]);
+        let headers = sse.customer_headers();
+        assert_eq!(
+            headers,
+            vec![
+                (
+                    "x-amz-server-side-encryption-customer-algorithm".to_string(),
+                    "AES256".to_string()
+                ),
+                (
+                    "x-amz-server-side-encryption-customer-key".to_string(),
+                    KEY.to_string()
+                ),
+                (
+                    "x-amz-server-side-encryption-customer-key-MD5".to_string(),
+                    "4Funlf7OsLF0HL+vKU+fkg==".to_string()
+                ),
+            ]
+        );
+        assert_eq!(sse.object_headers(), headers);
+        assert!(Sse::S3.customer_headers().is_empty());
+        assert_eq!(Sse::Kms(Some("key".to_string())).object_headers().len(), 2);
+    }
+
+    #[test]
+    fn copy_sources_are_read_with_the_customer_key() {
+        let headers = copy_source_headers(&Some(Sse::Customer(vec![1; 32])));
+        assert_eq!(
+            headers[1],
+            (
+                "x-amz-copy-source-server-side-encryption-customer-key".to_string(),
+                KEY.to_string()
+            )
+        );
+        assert_eq!(headers.len(), 3);
+        assert!(copy_source_headers(&Some(Sse::S3)).is_empty());
+        assert!(copy_source_headers(&None).is_empty());
+    }
+
+    fn head(headers: &[(&'static str, &str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn matches_objects_encrypted_the_same_way() {
+        let arn = "arn:aws:kms:eu-central-1:1234:key/abcd";
+        let s3 = head(&[("x-amz-server-side-encryption", "AES256")]);
+        let kms = head(&[
+            ("x-amz-server-side-encryption", "aws:kms"),
+            ("x-amz-server-side-encryption-aws-kms-key-id", arn),
+        ]);
+        let customer = head(&[
+            ("x-amz-server-side-encryption-customer-algorithm", "AES256"),
+            (
+                "x-amz-server-side-encryption-customer-key-MD5",
+                "4Funlf7OsLF0HL+vKU+fkg==",
+            ),
+        ]);
+        let plain = HeaderMap::new();
+
+        assert!(Sse::S3.matches(&s3));
+        assert!(!Sse::S3.matches(&kms));
+        assert!(!Sse::S3.matches(&plain));
+        assert!(Sse::Kms(None).matches(&kms));
+        assert!(Sse::Kms(Some(arn.to_string())).matches(&kms));
+        assert!(Sse::Kms(Some("abcd".to_string())).matches(&kms));
+        assert!(!Sse::Kms(Some("bcd".to_string())).matches(&kms));
+        assert!(!Sse::Kms(None).matches(&s3));
+        assert!(Sse::Customer(vec![1; 32]).matches(&customer));
+        assert!(!Sse::Customer(vec![2; 32]).matches(&customer));
+        assert!(!Sse::Customer(vec![1; 32]).matches(&plain));
+        assert!(matches(&None, &kms));
+        assert!(matches(&None, &plain));
+    }
+}
@@ -0,0 +1,615 @@
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::ops::Range;
+use std::process::exit;
+
+use bytes::Bytes;
+use indicatif::ProgressBar;
+use percent_encoding::utf8_percent_encode;
+use reqwest::header::{HeaderMap, ETAG};
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode};
+use rusty_s3::actions::{
+    AbortMultipartUpload, CompleteMultipartUpload, CreateMultipartUpload, HeadObject, ListParts,
+    PutObject, S3Action, UploadPart,
+};
+use rusty_s3::{Bucket, Map};
+use sha2::{Digest, Sha256};
+
+use crate::checksum::{self, CHECKSUM_HEADER};
+use crate::link::PATH_SEGMENT;
+use crate::parts::MAX_PART_SIZE;
+use crate::progress::ProgressBody;
+use crate::sse;
+use crate::upload_actor::{s3_error_code, spawn_upload_actor, PartError, UploadedPart};
+use crate::{config::Config, ONE_HOUR};
+
+const CHECKSUM_ALGORITHM_HEADER: &str = "x-amz-checksum-algorithm";
+const CHECKSUM_ALGORITHM: &str = "SHA256";
+const COPY_SOURCE_HEADER: &str = "x-amz-copy-source";
+const COPY_SOURCE_RANGE_HEADER: &str = "x-amz-copy-source-range";
+const METADATA_DIRECTIVE_HEADER: &str = "x-amz-metadata-directive";
+/// Objects up to this size can be copied with a single `CopyObject`.
+pub const MAX_COPY_OBJECT_SIZE: u64 = MAX_PART_SIZE;
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// A request to S3 failed, with a description of what was attempted
+    Request(&'static str, String),
+    /// A part could not be uploaded, even after retrying
+    Part(PartError),
+    /// Reading or zipping the input failed
+    Input(String),
+    /// The server reports something else than what was uploaded
+    Verify(String),
+    /// The user asked the process to stop
+    Cancelled,
+}
+
+impl Display for UploadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Request(what, e) => write!(f, "error {}: {}", what, e),
+            UploadError::Part(e) => write!(f, "error uploading file: {}", e),
+            UploadError::Input(e) => write!(f, "error reading input: {}", e),
+            UploadError::Verify(e) => write!(f, "error verifying upload: {}", e),
+            UploadError::Cancelled => write!(f, "upload cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<PartError> for UploadError {
+    fn from(e: PartError) -> Self {
+        UploadError::Part(e)
+    }
+}
+
+/// What was uploaded, to compare it with what the server reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uploaded {
+    /// Hex encoded SHA-256 of the whole object
+    pub sha256: String,
+    pub size: u64,
+    /// The `x-amz-checksum-sha256` the server should report for the object
+    pub checksum: String,
+    /// The ETag the server should report for the object, if it is known
+    pub etag: Option<String>,
+}
+
+/// Extra headers for `PutObject` and `CreateMultipartUpload`, like the content type.
+/// They are signed, so the server rejects the request if they are changed.
+pub type ObjectHeaders = Vec<(String, String)>;
+
+/// Sign `headers` with an action. They must then be sent with [`with_headers`].
+pub fn sign_headers<'a>(map: &mut Map<'a>, headers: &'a ObjectHeaders) {
+    for (name, value) in headers {
+        map.insert(name, value);
+    }
+}
+
+/// Add `headers` to a request whose URL was signed with them.
+pub fn with_headers(mut request: RequestBuilder, headers: &ObjectHeaders) -> RequestBuilder {
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// Send `request` and fail on a non-2xx status.
+pub async fn send(request: RequestBuilder, what: &'static str) -> Result<Response, UploadError> {
+    request
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| UploadError::Request(what, e.to_string()))
+}
+
+/// Upload `body` to `path` with a single PUT request, advancing `progress_bar`
+/// as the bytes are sent.
+pub async fn put_object(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    path: &str,
+    headers: &ObjectHeaders,
+    body: Vec<u8>,
+    progress_bar: &ProgressBar,
+) -> Result<Uploaded, UploadError> {
+    let sha256 = Sha256::digest(&body);
+    let checksum = checksum::encode(&sha256);
+    let headers = &[headers.clone(), sse::object_headers(&config.sse)].concat();
+    let mut action = PutObject::new(bucket, Some(&config.credentials), path);
+    action.headers_mut().insert(CHECKSUM_HEADER, &checksum);
+    sign_headers(action.headers_mut(), headers);
+    let url = action.sign(ONE_HOUR);
+    let content_len = body.len();
+    let (body, _) = ProgressBody::new(Bytes::from(body), progress_bar.clone());
+    let resp = send(
+        with_headers(client.put(url), headers)
+            .header(CHECKSUM_HEADER, &checksum)
+            .body(Body::wrap(body)),
+        "uploading file",
+    )
+    .await?;
+    let etag = resp
+        .headers()
+        .get(ETAG)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.to_string());
+    Ok(Uploaded {
+        sha256: format!("{:x}", sha256),
+        size: content_len as u64,
+        checksum,
+        etag,
+    })
+}
+
+/// The `x-amz-copy-source` header value for `key` in `bucket`.
+pub fn copy_source(bucket: &Bucket, key: &str) -> String {
+    let key: Vec<_> = key
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect();
+    format!("{}/{}", bucket.name(), key.join("/"))
+}
+
+/// Copy `source` to `path` on the server with `CopyObject`, which works for
+/// objects of up to 5 GiB. The copy gets `headers` instead of the ones of `source`.
+pub async fn copy_object(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    source: &str,
+    path: &str,
+    headers: &ObjectHeaders,
+) -> Result<(), UploadError> {
+    const WHAT: &str = "copying file";
+    let headers = &[
+        headers.clone(),
+        vec![
+            (COPY_SOURCE_HEADER.to_string(), copy_source(bucket, source)),
+            (METADATA_DIRECTIVE_HEADER.to_string(), "REPLACE".to_string()),
+        ],
+        sse::object_headers(&config.sse),
+        sse::copy_source_headers(&config.sse),
+    ]
+    .concat();
+    let mut action = PutObject::new(bucket, Some(&config.credentials), path);
+    sign_headers(action.headers_mut(), headers);
+    let url = action.sign(ONE_HOUR);
+    let body = send(with_headers(client.put(url), headers), WHAT)
+        .await?
+        .text()
+        .await
+        .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+    // a copy can fail after the server has answered with 200 OK
+    match s3_error_code(&body) {
+        Some(code) => Err(UploadError::Request(WHAT, code.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// The headers of the object at `key`.
+pub async fn head_object(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    key: &str,
+) -> Result<HeaderMap, UploadError> {
+    let headers = sse::customer_headers(&config.sse);
+    let mut action = HeadObject::new(bucket, Some(&config.credentials), key);
+    sign_headers(action.headers_mut(), &headers);
+    let url = action.sign(ONE_HOUR);
+    let resp = send(
+        with_headers(client.head(url), &headers),
+        "looking up object",
+    )
+    .await?;
+    Ok(resp.headers().clone())
+}
+
+/// A multipart upload that has been created on the server.
+pub struct MultipartUpload<'a> {
+    config: &'a Config,
+    bucket: &'a Bucket,
+    client: &'a Client,
+    path: &'a str,
+    upload_id: String,
+}
+
+impl<'a> MultipartUpload<'a> {
+    pub async fn create(
+        config: &'a Config,
+        bucket: &'a Bucket,
+        client: &'a Client,
+        path: &'a str,
+        headers: &ObjectHeaders,
+    ) -> Result<Self, UploadError> {
+        const WHAT: &str = "creating multipart upload";
+        let headers = &[headers.clone(), sse::object_headers(&config.sse)].concat();
+        let mut action = CreateMultipartUpload::new(bucket, Some(&config.credentials), path);
+        // every part is then sent with its checksum
+        action
+            .headers_mut()
+            .insert(CHECKSUM_ALGORITHM_HEADER, CHECKSUM_ALGORITHM);
+        sign_headers(action.headers_mut(), headers);
+
+        let url = action.sign(ONE_HOUR);
+
+        let body = send(
+            with_headers(client.post(url), headers)
+                .header(CHECKSUM_ALGORITHM_HEADER, CHECKSUM_ALGORITHM),
+            WHAT,
+        )
+        .await?
+        .text()
+        .await
+        .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+
+        let upload = CreateMultipartUpload::parse_response(&body)
+            .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+
+        Ok(Self {
+            config,
+            bucket,
+            client,
+            path,
+            upload_id: upload.upload_id().to_string(),
+        })
+    }
+
+    /// Continue a multipart upload that was created by an earlier run.
+    pub fn resume(
+        config: &'a Config,
+        bucket: &'a Bucket,
+        client: &'a Client,
+        path: &'a str,
+        upload_id: String,
+    ) -> Self {
+        Self {
+            config,
+            bucket,
+            client,
+            path,
+            upload_id,
+        }
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    /// List the parts the server already has as part number (starting at 0), ETag and size.
+    /// Returns `None` if the upload does not exist anymore.
+    pub async fn list_parts(&self) -> Result<Option<Vec<(u16, String, u64)>>, UploadError> {
+        const WHAT: &str = "listing uploaded parts";
+        let mut parts = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut action = ListParts::new(
+                self.bucket,
+                Some(&self.config.credentials),
+                self.path,
+                &self.upload_id,
+            );
+            if let Some(marker) = marker {
+                action.set_part_number_marker(marker);
+            }
+            let url = action.sign(ONE_HOUR);
+            let resp = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+            if resp.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let body = resp
+                .error_for_status()
+                .map_err(|e| UploadError::Request(WHAT, e.to_string()))?
+                .text()
+                .await
+                .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+            let resp = ListParts::parse_response(&body)
+                .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+            parts.extend(
+                resp.parts
+                    .into_iter()
+                    .map(|p| (p.number - 1, p.etag, p.size)),
+            );
+            marker = resp.next_part_number_marker;
+            if marker.is_none() {
+                return Ok(Some(parts));
+            }
+        }
+    }
+
+    /// Upload every part received on `upload_rx` with `num_threads` upload actors,
+    /// advancing `progress_bar` as the bytes are sent. `on_part` is called for every part as soon as it is uploaded.
+    /// Returns the uploaded parts, or the first part that failed.
+    pub async fn upload_parts(
+        &self,
+        upload_rx: flume::Receiver<(u16, Vec<u8>)>,
+        num_threads: usize,
+        progress_bar: &ProgressBar,
+        mut on_part: impl FnMut(&UploadedPart),
+    ) -> Result<Vec<UploadedPart>, UploadError> {
+        let (etag_tx, etag_rx) = flume::unbounded();
+        for _ in 0..num_threads {
+            spawn_upload_actor(
+                self.config.clone(),
+                self.bucket.clone(),
+                self.path.to_string(),
+                self.client.clone(),
+                self.upload_id.clone(),
+                upload_rx.clone(),
+                etag_tx.clone(),
+                progress_bar.clone(),
+            );
+        }
+        drop(upload_rx);
+        drop(etag_tx);
+        let mut parts = Vec::new();
+        while let Ok(part) = etag_rx.recv_async().await {
+            let part = part?;
+            on_part(&part);
+            parts.push(part);
+        }
+        Ok(parts)
+    }
+
+    /// Copy the bytes `range` of the object at `source` into part `number`
+    /// (starting at 0) with `UploadPartCopy`.
+    pub async fn copy_part(
+        &self,
+        number: u16,
+        source: &str,
+        range: Range<u64>,
+    ) -> Result<UploadedPart, UploadError> {
+        const WHAT: &str = "copying part";
+        let headers = &[
+            vec![
+                (
+                    COPY_SOURCE_HEADER.to_string(),
+                    copy_source(self.bucket, source),
+                ),
+                (
+                    COPY_SOURCE_RANGE_HEADER.to_string(),
+                    format!("bytes={}-{}", range.start, range.end - 1),
+                ),
+            ],
+            sse::customer_headers(&self.config.sse),
+            sse::copy_source_headers(&self.config.sse),
+        ]
+        .concat();
+        let mut action = UploadPart::new(
+            self.bucket,
+            Some(&self.config.credentials),
+            self.path,
+            number + 1,
+            &self.upload_id,
+        );
+        sign_headers(action.headers_mut(), headers);
+        let url = action.sign(ONE_HOUR);
+        let body = send(with_headers(self.client.put(url), headers), WHAT)
+            .await?
+            .text()
+            .await
+            .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+        let etag = xml_value(&body, "ETag").ok_or_else(|| {
+            UploadError::Request(WHAT, format!("no ETag for part {}", number + 1))
+        })?;
+        Ok(UploadedPart {
+            number,
+            etag: etag.replace("&quot;", "\""),
+            // servers that do not compute one get a part without a checksum
+            checksum: xml_value(&body, "ChecksumSHA256")
+                .unwrap_or_default()
+                .to_string(),
+            size: range.end - range.start,
+        })
+    }
+
+    /// Complete the upload with the given parts, in any order. `sha256` is the
+    /// hex encoded SHA-256 of the whole object.
+    pub async fn complete(
+        &self,
+        parts: &mut [UploadedPart],
+        sha256: String,
+    ) -> Result<Uploaded, UploadError> {
+        const WHAT: &str = "completing multipart upload";
+        parts.sort_by_key(|p| p.number);
+        let checksum = checksum::composite_checksum(parts.iter().map(|p| p.checksum.as_str()))
+            .map_err(|e| UploadError::Request(WHAT, e))?;
+        let mut action = CompleteMultipartUpload::new(
+            self.bucket,
+            Some(&self.config.credentials),
+            self.path,
+            &self.upload_id,
+            parts.iter().map(|p| p.etag.as_str()),
+        );
+        // SSE-C uploads with checksums need the key to complete
+        let headers = sse::customer_headers(&self.config.sse);
+        sign_headers(action.headers_mut(), &headers);
+        let url = action.sign(ONE_HOUR);
+
+        let body = send(
+            with_headers(self.client.post(url), &headers).body(complete_body(parts)),
+            WHAT,
+        )
+        .await?
+        .text()
+        .await
+        .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+        // like a copy, completing can fail after the server has answered with 200 OK
+        if let Some(code) = s3_error_code(&body) {
+            return Err(UploadError::Request(WHAT, code.to_string()));
+        }
+        Ok(Uploaded {
+            sha256,
+            size: parts.iter().map(|p| p.size).sum(),
+            checksum,
+            etag: checksum::multipart_etag(parts.iter().map(|p| p.etag.as_str())),
+        })
+    }
+
+    /// Abort the upload, so the server drops all parts uploaded so far.
+    pub async fn abort(&self) -> Result<(), UploadError> {
+        let action = AbortMultipartUpload::new(
+            self.bucket,
+            Some(&self.config.credentials),
+            self.path,
+            &self.upload_id,
+        );
+        let url = action.sign(ONE_HOUR);
+        send(self.client.delete(url), "aborting multipart upload").await?;
+        Ok(())
+    }
+
+    /// Run `transfer`, which uploads and completes this upload, and return its
+    /// result. If it fails or the process is interrupted, the upload is aborted
+    /// and the process exits.
+    ///
+    /// A `resumable` upload is kept instead, so it can be continued later.
+    pub async fn finish_or_abort<T>(
+        &self,
+        resumable: bool,
+        transfer: impl Future<Output = Result<T, UploadError>>,
+    ) -> T {
+        let (message, code) = tokio::select! {
+            res = transfer => match res {
+                Ok(t) => return t,
+                Err(e) => (e.to_string(), 1),
+            },
+            code = shutdown_signal() => ("upload cancelled".to_string(), code),
+        };
+        eprintln!("{}", message);
+        if resumable {
+            eprintln!("run the same command with --resume to continue the upload");
+        } else {
+            match self.abort().await {
+                Ok(()) => eprintln!("aborted multipart upload"),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        exit(code);
+    }
+}
+
+/// The body of a `CompleteMultipartUpload` request. Unlike the one of
+/// rusty-s3, it includes the checksum of every part, which S3 requires for
+/// uploads created with a checksum algorithm.
+fn complete_body(parts: &[UploadedPart]) -> String {
+    let mut body = String::from("<CompleteMultipartUpload>");
+    for part in parts {
+        body.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag>",
+            part.number + 1,
+            part.etag
+        ));
+        if !part.checksum.is_empty() {
+            body.push_str(&format!(
+                "<ChecksumSHA256>{}</ChecksumSHA256>",
+                part.checksum
+            ));
+        }
+        body.push_str("</Part>");
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+/// The text of the first `<tag>` in an XML response.
+fn xml_value<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{tag}>");
+    let start = body.find(&open)? + open.len();
+    let end = start + body[start..].find(&format!("</{tag}>"))?;
+    Some(&body[start..end])
+}
+
+/// Wait until the user asks the process to stop.
+/// Returns the exit code for the received signal.
+pub async fn shutdown_signal() -> i32 {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("warn: could not listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return 130;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => 130,
+            _ = terminate.recv() => 143,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        130
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_s3::{Bucket, UrlStyle};
+
+    use super::{complete_body, copy_source, xml_value};
+    use crate::upload_actor::UploadedPart;
+
+    #[test]
+    fn complete_body_lists_checksums() {
+        let parts = [UploadedPart {
+            number: 0,
+            etag: "\"etag\"".to_string(),
+            checksum: "c2hh".to_string(),
+            size: 3,
+        }];
+
+        assert_eq!(
+            complete_body(&parts),
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"etag\"</ETag><ChecksumSHA256>c2hh</ChecksumSHA256></Part></CompleteMultipartUpload>"
+        );
+    }
+
+    #[test]
+    fn copied_parts_may_lack_a_checksum() {
+        let parts = [UploadedPart {
+            number: 1,
+            etag: "\"etag\"".to_string(),
+            checksum: String::new(),
+            size: 3,
+        }];
+
+        assert_eq!(
+            complete_body(&parts),
+            "<CompleteMultipartUpload><Part><PartNumber>2</PartNumber><ETag>\"etag\"</ETag></Part></CompleteMultipartUpload>"
+        );
+    }
+
+    #[test]
+    fn copies_name_their_encoded_source() {
+        let bucket = Bucket::new(
+            "https://s3.example.com".parse().unwrap(),
+            UrlStyle::Path,
+            "bucket",
+            "region",
+        )
+        .unwrap();
+        assert_eq!(
+            copy_source(&bucket, "01KK3RNR08TGMCJ9E1W0949R1Y/100% ✓.pdf"),
+            "bucket/01KK3RNR08TGMCJ9E1W0949R1Y/100%25%20%E2%9C%93.pdf"
+        );
+
+        let body = "<CopyPartResult><ETag>&quot;abc&quot;</ETag><ChecksumSHA256>c2hh</ChecksumSHA256></CopyPartResult>";
+        assert_eq!(xml_value(body, "ETag"), Some("&quot;abc&quot;"));
+        assert_eq!(xml_value(body, "ChecksumSHA256"), Some("c2hh"));
+        assert_eq!(xml_value(body, "LastModified"), None);
+    }
+}
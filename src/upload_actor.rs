@@ -1,9 +1,66 @@
-use std::process::exit;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
-use reqwest::{header::ETAG, Client};
+use bytes::Bytes;
+use indicatif::ProgressBar;
+use reqwest::{header::ETAG, Body, Client, StatusCode};
 use rusty_s3::{actions::UploadPart, Bucket, S3Action};
 
-use crate::{config::Config, ONE_HOUR};
+use crate::checksum::{checksum, CHECKSUM_HEADER};
+use crate::sse;
+use crate::upload::{sign_headers, with_headers};
+use crate::{config::Config, progress::ProgressBody, ONE_HOUR};
+
+/// Delay before the first retry, doubled for every further attempt.
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound for the delay between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Time a part may take on top of sending it at [`MIN_THROUGHPUT`].
+const PART_TIMEOUT: Duration = Duration::from_secs(60);
+/// Bytes per second a part must at least be sent with, a slower one has
+/// stalled and is sent again.
+const MIN_THROUGHPUT: u64 = 64 * 1024;
+
+/// A part the server has accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadedPart {
+    /// The part number (starting at 0)
+    pub number: u16,
+    pub etag: String,
+    /// Base64 encoded SHA-256 of the part
+    pub checksum: String,
+    pub size: u64,
+}
+
+/// A part that could not be uploaded, even after retrying.
+#[derive(Debug)]
+pub struct PartError {
+    /// The S3 part number (starting at 1)
+    pub part_number: u16,
+    pub attempts: u32,
+    pub reason: String,
+}
+
+impl Display for PartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "part {} failed after {} attempt(s): {}",
+            self.part_number, self.attempts, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PartError {}
+
+/// Why a single attempt to upload a part failed.
+enum AttemptError {
+    /// Worth trying again, e.g. a timeout, a 5xx or S3 asking us to slow down
+    Transient(String),
+    /// Will fail again no matter how often we retry
+    Fatal(String),
+}
 
 struct UploadActor {
     client: Client,
@@ -11,6 +68,7 @@ struct UploadActor {
     upload_id: String,
     config: Config,
     path: String,
+    progress_bar: ProgressBar,
 }
 
 impl UploadActor {
@@ -20,6 +78,7 @@ impl UploadActor {
         path: String,
         client: Client,
         upload_id: String,
+        progress_bar: ProgressBar,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
             client,
@@ -27,50 +86,147 @@ impl UploadActor {
             upload_id,
             config,
             path,
+            progress_bar,
         })
     }
 
-    async fn upload_part(&self, part_number: u16, chunk: &[u8]) -> String {
-        let action = UploadPart::new(
+    /// Upload a part, retrying transient failures with exponential backoff.
+    async fn upload_part(&self, part_number: u16, chunk: Bytes) -> Result<UploadedPart, PartError> {
+        let checksum = checksum(&chunk);
+        let max_attempts = self.config.retries + 1;
+        let mut attempt = 1;
+        loop {
+            let reason = match self
+                .try_upload_part(part_number, chunk.clone(), &checksum)
+                .await
+            {
+                Ok(etag) => {
+                    return Ok(UploadedPart {
+                        number: part_number,
+                        etag,
+                        checksum,
+                        size: chunk.len() as u64,
+                    })
+                }
+                Err(AttemptError::Transient(reason)) if attempt < max_attempts => reason,
+                Err(AttemptError::Transient(reason)) | Err(AttemptError::Fatal(reason)) => {
+                    return Err(PartError {
+                        part_number: part_number + 1,
+                        attempts: attempt,
+                        reason,
+                    })
+                }
+            };
+            let delay = backoff(attempt);
+            eprintln!(
+                "warn: part {} failed ({}), retrying in {:?} ...",
+                part_number + 1,
+                reason,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn try_upload_part(
+        &self,
+        part_number: u16,
+        chunk: Bytes,
+        checksum: &str,
+    ) -> Result<String, AttemptError> {
+        let mut action = UploadPart::new(
             &self.bucket,
             Some(&self.config.credentials),
             &self.path,
             part_number + 1,
             &self.upload_id,
         );
+        // the server recomputes the checksum and rejects the part if it differs
+        action.headers_mut().insert(CHECKSUM_HEADER, checksum);
+        let headers = sse::customer_headers(&self.config.sse);
+        sign_headers(action.headers_mut(), &headers);
         let url = action.sign(ONE_HOUR);
-        let resp = match self.client.put(url).body(chunk.to_vec()).send().await {
+        let chunk_len = chunk.len() as u64;
+        let (body, sent) = ProgressBody::new(chunk, self.progress_bar.clone());
+        let result = with_headers(self.client.put(url), &headers)
+            .header(CHECKSUM_HEADER, checksum)
+            .timeout(part_timeout(chunk_len))
+            .body(Body::wrap(body))
+            .send()
+            .await;
+        let failed = !matches!(&result, Ok(resp) if resp.status().is_success());
+        if failed {
+            // this part will be sent again, take it back from the progress bar
+            self.progress_bar.dec(sent.load(Ordering::Relaxed));
+        }
+        let resp = match result {
             Ok(r) => r,
-            Err(e) => {
-                println!("error uploading chunk: {}", e);
-                exit(1);
-            }
-        };
-        let resp = match resp.error_for_status() {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("error uploading chunk: {}", e);
-                exit(1);
-            }
+            // connection resets, timeouts and the like
+            Err(e) => return Err(AttemptError::Transient(e.to_string())),
         };
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            let reason = match s3_error_code(&body) {
+                Some(code) => format!("{} ({})", status, code),
+                None => status.to_string(),
+            };
+            return Err(if is_transient(status, &body) {
+                AttemptError::Transient(reason)
+            } else {
+                AttemptError::Fatal(reason)
+            });
+        }
         let etag = match resp.headers().get(ETAG) {
             Some(e) => e,
-            None => {
-                println!("error uploading chunk: no etag in response");
-                exit(1);
-            }
-        };
-        let etag = match etag.to_str() {
-            Ok(e) => e,
-            Err(e) => {
-                println!("error uploading chunk: {}", e);
-                exit(1);
-            }
+            None => return Err(AttemptError::Transient("no etag in response".to_string())),
         };
-        etag.to_string()
+        match etag.to_str() {
+            Ok(e) => Ok(e.to_string()),
+            Err(e) => Err(AttemptError::Fatal(e.to_string())),
+        }
+    }
+}
+
+/// Extract the `<Code>` from an S3 error response.
+pub fn s3_error_code(body: &str) -> Option<&str> {
+    let start = body.find("<Code>")? + "<Code>".len();
+    let end = start + body[start..].find("</Code>")?;
+    Some(&body[start..end])
+}
+
+/// Whether a failed request is worth retrying.
+fn is_transient(status: StatusCode, body: &str) -> bool {
+    if status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+    {
+        return true;
     }
+    // S3 reports some transient conditions with a 400, a part that was
+    // corrupted on the way is sent again from memory
+    matches!(
+        s3_error_code(body),
+        Some("SlowDown" | "RequestTimeout" | "InternalError" | "ServiceUnavailable" | "BadDigest")
+    )
+}
+
+/// How long uploading a part of `len` bytes may take before it is given up
+/// and retried.
+fn part_timeout(len: u64) -> Duration {
+    PART_TIMEOUT + Duration::from_secs(len / MIN_THROUGHPUT)
 }
 
+/// Exponential backoff with full jitter for the given (1-based) attempt.
+fn backoff(attempt: u32) -> Duration {
+    let max = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_BACKOFF);
+    max.mul_f64(fastrand::f64())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_upload_actor(
     config: Config,
     bucket: Bucket,
@@ -78,16 +234,73 @@ pub fn spawn_upload_actor(
     client: Client,
     upload_id: String,
     upload_rx: flume::Receiver<(u16, Vec<u8>)>,
-    etag_tx: flume::Sender<(u16, String)>,
+    etag_tx: flume::Sender<Result<UploadedPart, PartError>>,
+    progress_bar: ProgressBar,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let actor = UploadActor::new(config, bucket, path, client, upload_id)
+        let actor = UploadActor::new(config, bucket, path, client, upload_id, progress_bar)
             .await
             .unwrap();
-        while let Ok((part_number, chunk)) = upload_rx.recv() {
-            let etag = actor.upload_part(part_number, &chunk).await;
+        while let Ok((part_number, chunk)) = upload_rx.recv_async().await {
+            let result = actor.upload_part(part_number, Bytes::from(chunk)).await;
             // println!("uploaded part {} after {:?}", part_number, now.elapsed());
-            etag_tx.send((part_number, etag)).unwrap();
+            let failed = result.is_err();
+            if etag_tx.send_async(result).await.is_err() || failed {
+                // the upload failed or was given up, stop taking parts
+                break;
+            }
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use std::time::Duration;
+
+    use super::{backoff, is_transient, part_timeout, s3_error_code, MAX_BACKOFF};
+
+    #[test]
+    fn extracts_s3_error_code() {
+        let body = "<?xml version=\"1.0\"?><Error><Code>SlowDown</Code><Message>Please reduce your request rate.</Message></Error>";
+
+        assert_eq!(s3_error_code(body), Some("SlowDown"));
+        assert_eq!(s3_error_code(""), None);
+    }
+
+    #[test]
+    fn classifies_transient_failures() {
+        assert!(is_transient(StatusCode::SERVICE_UNAVAILABLE, ""));
+        assert!(is_transient(StatusCode::INTERNAL_SERVER_ERROR, ""));
+        assert!(is_transient(
+            StatusCode::BAD_REQUEST,
+            "<Error><Code>RequestTimeout</Code></Error>"
+        ));
+        assert!(is_transient(
+            StatusCode::BAD_REQUEST,
+            "<Error><Code>BadDigest</Code></Error>"
+        ));
+        assert!(!is_transient(StatusCode::FORBIDDEN, ""));
+        assert!(!is_transient(
+            StatusCode::NOT_FOUND,
+            "<Error><Code>NoSuchUpload</Code></Error>"
+        ));
+    }
+
+    #[test]
+    fn large_parts_get_more_time() {
+        assert_eq!(part_timeout(0), Duration::from_secs(60));
+        assert_eq!(
+            part_timeout(64 * 1024 * 1024),
+            Duration::from_secs(60 + 1024)
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        for attempt in 1..100 {
+            assert!(backoff(attempt) <= MAX_BACKOFF);
+        }
+    }
+}
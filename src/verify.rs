@@ -0,0 +1,119 @@
+// check an uploaded object against what was sent, before handing out a link
+
+use reqwest::header::{CONTENT_LENGTH, ETAG};
+use reqwest::Client;
+use rusty_s3::actions::{HeadObject, S3Action};
+use rusty_s3::Bucket;
+
+use crate::checksum::CHECKSUM_HEADER;
+use crate::sse;
+use crate::upload::{sign_headers, with_headers, UploadError, Uploaded};
+use crate::{config::Config, ONE_HOUR};
+
+/// Ask the server to include the checksum in the response.
+const CHECKSUM_MODE_HEADER: &str = "x-amz-checksum-mode";
+
+/// Send `HeadObject` for `path` and compare the result with `uploaded`.
+/// Returns what could be compared.
+pub async fn verify(
+    config: &Config,
+    bucket: &Bucket,
+    client: &Client,
+    path: &str,
+    uploaded: &Uploaded,
+) -> Result<&'static str, UploadError> {
+    const WHAT: &str = "verifying upload";
+    let headers = sse::customer_headers(&config.sse);
+    let mut action = HeadObject::new(bucket, Some(&config.credentials), path);
+    action.headers_mut().insert(CHECKSUM_MODE_HEADER, "ENABLED");
+    sign_headers(action.headers_mut(), &headers);
+    let url = action.sign(ONE_HOUR);
+    let resp = with_headers(client.head(url), &headers)
+        .header(CHECKSUM_MODE_HEADER, "ENABLED")
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| UploadError::Request(WHAT, e.to_string()))?;
+
+    let header = |name| resp.headers().get(name).and_then(|v| v.to_str().ok());
+    let size = header(CONTENT_LENGTH.as_str()).and_then(|s| s.parse().ok());
+    compare(
+        uploaded,
+        size,
+        header(CHECKSUM_HEADER),
+        header(ETAG.as_str()),
+    )
+    .map_err(UploadError::Verify)
+}
+
+/// Compare what the server reports with what was uploaded.
+/// Returns what could be compared, or what differs.
+fn compare(
+    uploaded: &Uploaded,
+    size: Option<u64>,
+    checksum: Option<&str>,
+    etag: Option<&str>,
+) -> Result<&'static str, String> {
+    match size {
+        Some(size) if size == uploaded.size => {}
+        Some(size) => {
+            return Err(format!(
+                "the server has {} bytes, but {} bytes were uploaded",
+                size, uploaded.size
+            ))
+        }
+        None => return Err("the server did not report the size".to_string()),
+    }
+    // some servers leave out the number of parts of a composite checksum
+    let expected_checksum = uploaded.checksum.split('-').next().unwrap_or_default();
+    match (checksum, &uploaded.etag) {
+        (Some(checksum), _) if checksum == uploaded.checksum || checksum == expected_checksum => {
+            Ok("size and checksum match")
+        }
+        (Some(checksum), _) => Err(format!(
+            "the server has checksum {}, but {} was uploaded",
+            checksum, uploaded.checksum
+        )),
+        (None, Some(expected)) if etag == Some(expected.as_str()) => Ok("size and ETag match"),
+        (None, Some(expected)) => Err(format!(
+            "the server has ETag {}, but {} was expected",
+            etag.unwrap_or("none"),
+            expected
+        )),
+        (None, None) => Ok("size matches, the server reported no checksum"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare;
+    use crate::upload::Uploaded;
+
+    fn uploaded() -> Uploaded {
+        Uploaded {
+            sha256: "hex".to_string(),
+            size: 10,
+            checksum: "c2hh-2".to_string(),
+            etag: Some("\"md5-2\"".to_string()),
+        }
+    }
+
+    #[test]
+    fn matching_object_passes() {
+        let uploaded = uploaded();
+
+        assert!(compare(&uploaded, Some(10), Some("c2hh-2"), None).is_ok());
+        assert!(compare(&uploaded, Some(10), Some("c2hh"), None).is_ok());
+        assert!(compare(&uploaded, Some(10), None, Some("\"md5-2\"")).is_ok());
+    }
+
+    #[test]
+    fn differing_object_fails() {
+        let uploaded = uploaded();
+
+        assert!(compare(&uploaded, Some(9), Some("c2hh-2"), None).is_err());
+        assert!(compare(&uploaded, None, Some("c2hh-2"), None).is_err());
+        assert!(compare(&uploaded, Some(10), Some("b3RoZXI=-2"), None).is_err());
+        assert!(compare(&uploaded, Some(10), None, Some("\"md5-3\"")).is_err());
+    }
+}
@@ -1,27 +1,33 @@
 use fs_extra::dir;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::io::{self, prelude::*};
-use std::io::{Seek, Write};
+use indicatif::{MultiProgress, ProgressBar};
+use std::io::{self, Seek, Write};
 use std::iter::Iterator;
 use std::process::exit;
 use zip::result::ZipError;
 use zip::write::SimpleFileOptions;
-use zip::CompressionMethod;
+use zip::{CompressionMethod, ZipWriter};
 
+use std::collections::HashSet;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
+use crate::parts::PartWriter;
+use crate::progress::upload_progress_bar;
+
+/// Zip `src_dir` into `writer`, which uploads the archive while it is being written.
+/// The compression progress is shown in `progress`, next to the upload progress.
+/// Returns the hex encoded SHA-256 of the archive.
 pub(crate) fn zip_folder(
     src_dir: &str,
     cmp_mthd: CompressionMethod,
-) -> zip::result::ZipResult<Vec<u8>> {
+    writer: PartWriter,
+    progress: &MultiProgress,
+) -> zip::result::ZipResult<String> {
     if !Path::new(src_dir).is_dir() {
         return Err(ZipError::FileNotFound);
     }
 
-    let writer = std::io::Cursor::new(Vec::new());
-
     let dir_size = match dir::get_size(src_dir) {
         Ok(s) => s,
         Err(e) => {
@@ -29,13 +35,7 @@ pub(crate) fn zip_folder(
             exit(1);
         }
     };
-    let progress_bar = ProgressBar::new(dir_size);
-    progress_bar.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-        )
-        .unwrap()
-        .progress_chars("#>-"));
+    let progress_bar = progress.add(upload_progress_bar(Some(dir_size)));
 
     let walkdir = WalkDir::new(src_dir);
     let it = walkdir.into_iter();
@@ -43,39 +43,43 @@ pub(crate) fn zip_folder(
     let res = zip_dir(
         &mut it.filter_map(|e| e.ok()),
         src_dir,
-        writer,
+        Path::new(""),
+        ZipWriter::new_stream(writer),
         cmp_mthd,
         &progress_bar,
-    )?;
-    let inner = res.into_inner();
-    println!(
+    )?
+    .finish()?;
+    let (zip_size, sha256) = res.into_inner().finish()?;
+    progress_bar.println(format!(
         "Compression ratio: {}",
-        inner.len() as f64 / dir_size as f64
-    );
-    Ok(inner)
+        zip_size as f64 / dir_size as f64
+    ));
+    Ok(sha256)
 }
 
+/// Add the entries of `it`, which are all below `prefix`, to `zip` below `target`.
 fn zip_dir<T>(
     it: &mut dyn Iterator<Item = DirEntry>,
     prefix: &str,
-    writer: T,
+    target: &Path,
+    mut zip: ZipWriter<T>,
     method: zip::CompressionMethod,
     progress_bar: &ProgressBar,
-) -> zip::result::ZipResult<T>
+) -> zip::result::ZipResult<ZipWriter<T>>
 where
     T: Write + Seek,
 {
-    let mut zip = zip::ZipWriter::new(writer);
     let options = SimpleFileOptions::default()
         .compression_method(method)
         .unix_permissions(0o755);
 
-    let mut buffer = Vec::new();
     for entry in it {
         let path = entry.path();
-        let name = path
-            .strip_prefix(Path::new(prefix))
-            .expect("Path should start with prefix. This is a bug.");
+        let name = target.join(
+            path.strip_prefix(Path::new(prefix))
+                .expect("Path should start with prefix. This is a bug."),
+        );
+        let name = name.as_path();
 
         // Write file or directory explicitly
         // Some unzip tools unzip files with directory paths correctly, some do not!
@@ -90,13 +94,8 @@ where
             };
             #[allow(deprecated)]
             zip.start_file_from_path(name, options)?;
-            let mut f = File::open(path)?;
-
-            f.read_to_end(&mut buffer)?;
-            progress_bar.inc(buffer.len() as u64);
-
-            zip.write_all(&buffer)?;
-            buffer.clear();
+            let f = File::open(path)?;
+            io::copy(&mut progress_bar.wrap_read(f), &mut zip)?;
         } else if !name.as_os_str().is_empty() {
             // Only if not root! Avoids path spec / warning
             // and mapname conversion failed error on unzip
@@ -106,18 +105,115 @@ where
             zip.add_directory_from_path(name, options)?;
         }
     }
-    zip.finish()
+    Ok(zip)
+}
+
+/// Zip several files and directories into `writer`, each under its own name at
+/// the top level of the archive. The compression progress is shown in `progress`.
+/// Returns the hex encoded SHA-256 of the archive.
+pub(crate) fn zip_paths(
+    paths: &[PathBuf],
+    cmp_mthd: CompressionMethod,
+    writer: PartWriter,
+    progress: &MultiProgress,
+) -> zip::result::ZipResult<String> {
+    let total_size: u64 = paths
+        .iter()
+        .map(|path| match path.is_dir() {
+            true => dir::get_size(path).unwrap_or_default(),
+            false => path.metadata().map(|m| m.len()).unwrap_or_default(),
+        })
+        .sum();
+    let progress_bar = progress.add(upload_progress_bar(Some(total_size)));
+
+    let options = SimpleFileOptions::default()
+        .compression_method(cmp_mthd)
+        .unix_permissions(0o755);
+    let mut zip = ZipWriter::new_stream(writer);
+    let mut taken = HashSet::new();
+    for path in paths {
+        let name = unique_name(path, &mut taken);
+        if path.is_dir() {
+            let walkdir = WalkDir::new(path);
+            zip = zip_dir(
+                &mut walkdir.into_iter().filter_map(|e| e.ok()),
+                &path.to_string_lossy(),
+                Path::new(&name),
+                zip,
+                cmp_mthd,
+                &progress_bar,
+            )?;
+        } else {
+            progress_bar.println(format!("adding file {path:?} as {name:?} ...",));
+            let options = if path.metadata()?.len() >= 2u64.pow(32) {
+                options.large_file(true)
+            } else {
+                options
+            };
+            zip.start_file(name, options)?;
+            let f = File::open(path)?;
+            io::copy(&mut progress_bar.wrap_read(f), &mut zip)?;
+        }
+    }
+
+    let (zip_size, sha256) = zip.finish()?.into_inner().finish()?;
+    progress_bar.println(format!(
+        "Compression ratio: {}",
+        zip_size as f64 / total_size as f64
+    ));
+    Ok(sha256)
+}
+
+/// The top level name of `path` in an archive of several paths. Names that are
+/// already `taken` get a number, like `notes (2).txt`.
+fn unique_name(path: &Path, taken: &mut HashSet<String>) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".to_string());
+    // directories have no extension, also if their name contains a dot
+    let (stem, extension) = match name.split_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !path.is_dir() => {
+            (stem.to_string(), format!(".{extension}"))
+        }
+        _ => (name.clone(), String::new()),
+    };
+    let mut candidate = name;
+    let mut n = 2;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{stem} ({n}){extension}");
+        n += 1;
+    }
+    candidate
 }
 
-pub fn zip_file(src_file: &str, cmp_mthd: CompressionMethod) -> zip::result::ZipResult<Vec<u8>> {
+/// The name of an archive of several paths: the name of the directory they are
+/// all in, or `archive.zip`.
+pub(crate) fn archive_name(paths: &[PathBuf]) -> String {
+    let parent = paths[0].parent();
+    if paths.iter().all(|p| p.parent() == parent) {
+        if let Some(name) = parent.and_then(|p| p.file_name()) {
+            return format!("{}.zip", name.to_string_lossy());
+        }
+    }
+    "archive.zip".to_string()
+}
+
+/// Zip the single file `src_file` into `writer`, which uploads the archive while it
+/// is being written. The compression progress is shown in `progress`.
+/// Returns the hex encoded SHA-256 of the archive.
+pub fn zip_file(
+    src_file: &str,
+    cmp_mthd: CompressionMethod,
+    writer: PartWriter,
+    progress: &MultiProgress,
+) -> zip::result::ZipResult<String> {
     let src_file = Path::new(src_file);
     if !src_file.is_file() {
         eprintln!("error: trying to zip a directory as a file");
         return Err(ZipError::FileNotFound);
     }
 
-    let writer = std::io::Cursor::new(Vec::new());
-
     let file_size = match std::fs::metadata(src_file) {
         Ok(s) => s.len(),
         Err(e) => {
@@ -125,15 +221,9 @@ pub fn zip_file(src_file: &str, cmp_mthd: CompressionMethod) -> zip::result::Zip
             exit(1);
         }
     };
-    let progress_bar = ProgressBar::new(file_size);
-    progress_bar.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-        )
-        .unwrap()
-        .progress_chars("#>-"));
-
-    let mut zip = zip::ZipWriter::new(writer);
+    let progress_bar = progress.add(upload_progress_bar(Some(file_size)));
+
+    let mut zip = ZipWriter::new_stream(writer);
     let mut options = SimpleFileOptions::default()
         .compression_method(cmp_mthd)
         .unix_permissions(0o755);
@@ -141,37 +231,68 @@ pub fn zip_file(src_file: &str, cmp_mthd: CompressionMethod) -> zip::result::Zip
         options = options.large_file(true);
     }
 
-    let mut buffer = Vec::new();
     let path = Path::new("/").join(src_file.file_name().unwrap());
 
-    // Write file or directory explicitly
-    // Some unzip tools unzip files with directory paths correctly, some do not!
-    println!("Reading {path:?} ...");
+    println!("Zipping {path:?} ...");
     #[allow(deprecated)]
     zip.start_file_from_path(&path, options)?;
     let f = File::open(src_file)?;
+    io::copy(&mut progress_bar.wrap_read(f), &mut zip)?;
 
-    io::copy(&mut progress_bar.wrap_read(f), &mut buffer)?;
-
-    println!("Zipping {path:?} ...");
-    let progress_bar = ProgressBar::new(buffer.len() as u64);
-    progress_bar.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-        )
-        .unwrap()
-        .progress_chars("#>-"));
-    io::copy(
-        &mut progress_bar.wrap_read(&mut buffer.as_slice()),
-        &mut zip,
-    )?;
-    buffer.clear();
-
-    let zip = zip.finish()?;
-    let inner = zip.into_inner();
-    println!(
+    let (zip_size, sha256) = zip.finish()?.into_inner().finish()?;
+    progress_bar.println(format!(
         "Compression ratio: {}",
-        inner.len() as f64 / file_size as f64
-    );
-    Ok(inner)
+        zip_size as f64 / file_size as f64
+    ));
+    Ok(sha256)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+
+    use super::{archive_name, unique_name};
+
+    #[test]
+    fn duplicate_names_get_a_number() {
+        let mut taken = HashSet::new();
+        let names: Vec<_> = [
+            "/a/notes.txt",
+            "/b/notes.txt",
+            "/c/notes.txt",
+            "/a/archive.tar.gz",
+            "/b/archive.tar.gz",
+            "/a/.bashrc",
+            "/b/.bashrc",
+        ]
+        .iter()
+        .map(|p| unique_name(Path::new(p), &mut taken))
+        .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "notes.txt",
+                "notes (2).txt",
+                "notes (3).txt",
+                "archive.tar.gz",
+                "archive (2).tar.gz",
+                ".bashrc",
+                ".bashrc (2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn archive_is_named_after_common_directory() {
+        let paths = [
+            PathBuf::from("/home/me/project/a.log"),
+            PathBuf::from("/home/me/project/b"),
+        ];
+        assert_eq!(archive_name(&paths), "project.zip");
+
+        let paths = [PathBuf::from("/home/me/a.log"), PathBuf::from("/tmp/b")];
+        assert_eq!(archive_name(&paths), "archive.zip");
+    }
 }